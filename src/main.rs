@@ -1,6 +1,7 @@
 #![cfg_attr(coverage, feature(coverage_attribute))]
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use base64::prelude::*;
@@ -11,22 +12,27 @@ use rand::rngs::ChaCha20Rng;
 use rand::SeedableRng;
 #[cfg(feature = "webspy")] use rocket::fs::{FileServer,relative};
 use rocket::futures::channel::{self, mpsc::UnboundedSender};
-use rocket::futures::future::Either;
+use rocket::futures::future::{BoxFuture, Either, FutureExt};
 use rocket::futures::StreamExt;
-use rocket::http::Status;
+use rocket::http::{ContentType, Status};
+use rocket::http::uri::fmt::Path as UriPath;
+use rocket::http::uri::Segments;
 use rocket::request::FromRequest;
-use rocket::response::content::RawJson;
-use rocket::response::stream::{Event, EventStream};
+use rocket::response::stream::{ByteStream, Event, EventStream};
 use rocket::serde::json::Json;
 use rocket::tokio::time::Duration;
-use rocket::{catch, catchers, launch, post, routes, Build, Request, Rocket};
+use rocket::{catch, catchers, delete, get, launch, post, put, routes, Build, Request, Rocket};
 use rocket::State;
 use rocket_cors::{AllowedOrigins, CorsOptions};
 use serde::{Deserialize, Serialize};
-use shvclient::clientapi::{CallRpcMethodError, CallRpcMethodErrorKind, RpcCall};
+use shvclient::clientapi::{CallRpcMethodError, CallRpcMethodErrorKind};
 use shvclient::{ClientEvent, ConnectionFailedKind};
-use shvproto::RpcValue;
-use shvrpc::rpc::ShvRI;
+use shvproto::{MetaMap, RpcValue, Value};
+use shvrpc::rpc::{Glob, ShvRI};
+use shvrpc::metamethod::{AccessLevel, Flags, MetaMethod, SignalsDefinition};
+use shvrpc::rpcdiscovery::MethodInfo;
+use shvrpc::rpcmessage::{AbortParam, Response, RpcErrorCode, RpcErrorCodeKind, RqId};
+use shvrpc::RpcMessage;
 use shvrpc::RpcMessageMetaTags;
 use simple_logger::SimpleLogger;
 use tokio::sync::{Mutex, RwLock};
@@ -43,19 +49,26 @@ fn from_os_rng() -> ChaCha20Rng {
 
 type ClientCommandSender = shvclient::ClientCommandSender;
 
-async fn start_client(config: shvrpc::client::ClientConfig) -> Option<(ClientCommandSender, shvclient::ClientEventsReceiver)> {
+async fn start_client(config: shvrpc::client::ClientConfig, device: Option<DeviceNode>) -> Option<(ClientCommandSender, shvclient::ClientEventsReceiver)> {
     let (tx, rx) = rocket::futures::channel::oneshot::channel();
     tokio::spawn(async move {
-        shvclient::client::Client::new_plain()
-            .run_with_init(&config, |commands_tx, events_rx|
-                tx.send((commands_tx, events_rx))
-                .unwrap_or_else(|(commands_tx, _)| {
-                    warn!("Client channels dropped before handed to the caller. Terminating the client");
-                    commands_tx.terminate_client();
-                })
-            )
-            .await
-            .unwrap_or_else(|e| error!("Client finished with error: {e}"));
+        let init = |commands_tx: ClientCommandSender, events_rx| tx.send((commands_tx, events_rx))
+            .unwrap_or_else(|(commands_tx, _)| {
+                warn!("Client channels dropped before handed to the caller. Terminating the client");
+                commands_tx.terminate_client();
+            });
+        let result = match device {
+            None => shvclient::client::Client::new_plain()
+                .run_with_init(&config, init)
+                .await,
+            // The declared methods are exposed on the mount point of the client
+            Some(device) => shvclient::client::Client::new()
+                .app(shvclient::appnodes::DotAppNode::new(env!("CARGO_PKG_NAME")))
+                .mount_dynamic("", move |request, _| device.clone().process_request(request))
+                .run_with_init(&config, init)
+                .await,
+        };
+        result.unwrap_or_else(|e| error!("Client finished with error: {e}"));
         }
     );
     rx.await.ok()
@@ -70,6 +83,37 @@ struct ErrorResponseBody {
     detail: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     shv_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rpc_error: Option<Box<RpcErrorInfo>>,
+}
+
+/// Structured description of a failed method call
+#[derive(Clone,Debug,Deserialize,Serialize)]
+#[cfg_attr(test, derive(PartialEq))]
+struct RpcErrorInfo {
+    /// Numeric RPC error code, present only for errors received from the SHV tree
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<u32>,
+    name: String,
+    message: String,
+    path: String,
+    method: String,
+}
+
+impl From<&CallRpcMethodError> for RpcErrorInfo {
+    fn from(e: &CallRpcMethodError) -> Self {
+        let (code, message) = match e.error() {
+            CallRpcMethodErrorKind::RpcError(rpc_err) => (Some(u32::from(rpc_err.code)), rpc_err.message.clone()),
+            error => (None, error.to_string()),
+        };
+        Self {
+            code,
+            name: rpc_error_name(e.error()),
+            message,
+            path: e.path().into(),
+            method: e.method().into(),
+        }
+    }
 }
 
 fn err_response<T: AsRef<str>>(status: Status, detail: impl Into<Option<T>>) -> ErrorResponse {
@@ -79,6 +123,7 @@ fn err_response<T: AsRef<str>>(status: Status, detail: impl Into<Option<T>>) ->
             code: status.code,
             detail: detail.into().map_or_else(|| "Unspecified reason".to_string(), |v| v.as_ref().to_string()),
             shv_error: None,
+            rpc_error: None,
         })
     )
 }
@@ -88,7 +133,7 @@ struct SubscribeRequest<'t> {
     shv_ri: &'t str,
 }
 
-#[derive(shvproto::FromRpcValue, shvproto::ToRpcValue)]
+#[derive(Clone, shvproto::FromRpcValue, shvproto::ToRpcValue)]
 #[cfg_attr(test, derive(Debug, PartialEq))]
 struct SubscribeEvent {
     path: Option<String>,
@@ -97,29 +142,279 @@ struct SubscribeEvent {
 }
 
 
-#[post("/subscribe", data = "<request>")]
+#[post("/subscribe?<typed_json>", data = "<request>")]
 async fn api_subscribe(
     session: Session,
     request: Result<Json<SubscribeRequest<'_>>, rocket::serde::json::Error<'_>>,
+    last_event_id: Option<SubscribeLastEventId>,
+    typed_json: Option<bool>,
+    program_config: &State<Arc<ProgramConfig>>,
 ) -> Result<EventStream![], ErrorResponse>
 {
-    let Session(_session_id, SessionData { command_channel, session_channel, .. }) = session;
     let Json(SubscribeRequest { shv_ri }) = request
         .map_err(|e| err_response(Status::UnprocessableEntity, e.to_string()))?;
-    let shv_ri = ShvRI::try_from(shv_ri)
-        .map_err(|e| err_response(Status::UnprocessableEntity, e))?;
-    let mut subscriber = command_channel
-        .subscribe(shv_ri)
-        .await
-        .map_err(|e| err_response(Status::InternalServerError, e.to_string()))?;
+    subscribe_notifications(session, &[shv_ri], last_event_id, typed_json.unwrap_or(false), program_config.policy.clone()).await
+}
+
+/// A variant of `api_subscribe` usable by the browser `EventSource` API, which
+/// can issue only GET requests and cannot set custom headers.
+#[get("/subscribe?<shv_ri>&<typed_json>")]
+async fn api_subscribe_get(
+    session: EventSourceSession,
+    shv_ri: Vec<&str>,
+    last_event_id: Option<SubscribeLastEventId>,
+    typed_json: Option<bool>,
+    program_config: &State<Arc<ProgramConfig>>,
+) -> Result<EventStream![], ErrorResponse>
+{
+    if shv_ri.is_empty() {
+        return Err(err_response(Status::UnprocessableEntity, "Missing shv_ri query parameter"));
+    }
+    let EventSourceSession(session) = session;
+    subscribe_notifications(session, &shv_ri, last_event_id, typed_json.unwrap_or(false), program_config.policy.clone()).await
+}
+
+struct UnsubscribeNotifier(UnboundedSender<SessionEvent>);
+
+impl Drop for UnsubscribeNotifier {
+    fn drop(&mut self) {
+        self.0.unbounded_send(SessionEvent::Unsubscription)
+            .unwrap_or_else(|e| error!("Cannot send SessionEvent::Unsubscription: {e}"));
+    }
+}
+
+async fn subscribe_all(command_channel: &ClientCommandSender, shv_ris: &[ShvRI]) -> Result<Vec<shvclient::clientapi::Subscriber>, CallRpcMethodError> {
+    let mut subscribers = Vec::with_capacity(shv_ris.len());
+    for shv_ri in shv_ris {
+        subscribers.push(command_channel.subscribe(shv_ri.clone()).await?);
+    }
+    Ok(subscribers)
+}
+
+/// The value of the `Last-Event-ID` header sent by clients reconnecting to a
+/// subscribe stream, whose event IDs are `<stream ID>.<event ID>`
+struct SubscribeLastEventId {
+    stream_id: u64,
+    event_id: u64,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SubscribeLastEventId {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
+        use rocket::request::Outcome;
+        let Some(last_event_id) = req.headers().get_one("Last-Event-ID") else {
+            return Outcome::Forward(Status::Ok);
+        };
+        match last_event_id.split_once('.').map(|(stream_id, event_id)| (stream_id.parse(), event_id.parse())) {
+            Some((Ok(stream_id), Ok(event_id))) => Outcome::Success(SubscribeLastEventId { stream_id, event_id }),
+            _ => Outcome::Error((Status::BadRequest, ())),
+        }
+    }
+}
+
+/// The subscriptions of a subscribe stream with the log of their notifications
+struct SubscribeStream {
+    stream_id: u64,
+    shv_ris: Vec<ShvRI>,
+    event_log: SessionEventLog,
+    // Owns the Subscribers, aborting the task unsubscribes
+    task: tokio::task::JoinHandle<()>,
+    /// Closed when the task finishes
+    finished: tokio::sync::watch::Receiver<()>,
+}
+
+impl Drop for SubscribeStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A subscribe stream delivered to an HTTP client, which is detached from the
+/// client when dropped
+struct AttachedSubscribeStream {
+    streams: SubscribeStreams,
+    stream: Option<SubscribeStream>,
+}
+
+impl Drop for AttachedSubscribeStream {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            self.streams.detach(stream);
+        }
+    }
+}
+
+/// The subscribe streams of a session closed by the HTTP clients, which are
+/// kept for a while so that a reconnecting client can resume them
+#[derive(Clone)]
+struct SubscribeStreams {
+    detached: Arc<std::sync::Mutex<HashMap<u64, (u64, SubscribeStream)>>>,
+    last_stream_id: Arc<std::sync::atomic::AtomicU64>,
+    last_detach_id: Arc<std::sync::atomic::AtomicU64>,
+    resume_timeout: Duration,
+    history_size: usize,
+}
+
+impl SubscribeStreams {
+    fn new(resume_timeout: Duration, history_size: usize) -> Self {
+        Self {
+            detached: Default::default(),
+            last_stream_id: Default::default(),
+            last_detach_id: Default::default(),
+            resume_timeout,
+            history_size,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, (u64, SubscribeStream)>> {
+        self.detached.lock().expect("Subscribe streams lock is not poisoned")
+    }
+
+    async fn start(
+        &self,
+        command_channel: &ClientCommandSender,
+        shv_ris: Vec<ShvRI>,
+        broker_connection: tokio::sync::watch::Receiver<BrokerConnectionState>,
+        policy: Option<Policy>,
+    ) -> Result<SubscribeStream, CallRpcMethodError>
+    {
+        let subscribers = subscribe_all(command_channel, &shv_ris).await?;
+        let stream_id = self.last_stream_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let event_log = SessionEventLog::new(self.history_size);
+        let (finished_tx, finished) = tokio::sync::watch::channel(());
+        let task = tokio::spawn(forward_subscribe_notifications(
+                command_channel.clone(),
+                shv_ris.clone(),
+                subscribers,
+                broker_connection,
+                policy,
+                event_log.clone(),
+                finished_tx,
+        ));
+        Ok(SubscribeStream { stream_id, shv_ris, event_log, task, finished })
+    }
+
+    /// Takes the detached stream to resume it, if it has the same SHV RIs
+    fn resume(&self, stream_id: u64, shv_ris: &[ShvRI]) -> Option<SubscribeStream> {
+        let mut detached = self.lock();
+        if detached.get(&stream_id).is_none_or(|(_, stream)| stream.shv_ris != shv_ris) {
+            return None;
+        }
+        detached.remove(&stream_id).map(|(_, stream)| stream)
+    }
+
+    /// Keeps the stream for `resume_timeout` unless it has finished
+    fn detach(&self, stream: SubscribeStream) {
+        if stream.task.is_finished() {
+            return;
+        }
+        let stream_id = stream.stream_id;
+        let detach_id = self.last_detach_id.fetch_add(1, Ordering::SeqCst) + 1;
+        self.lock().insert(stream_id, (detach_id, stream));
+        let streams = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(streams.resume_timeout).await;
+            let mut detached = streams.lock();
+            // The stream may have been resumed and detached again in the meantime
+            if detached.get(&stream_id).is_some_and(|(id, _)| *id == detach_id) {
+                detached.remove(&stream_id);
+            }
+        });
+    }
+}
+
+/// Pushes the notifications of a subscribe stream to its log and subscribes
+/// again after a reconnect to the broker
+async fn forward_subscribe_notifications(
+    command_channel: ClientCommandSender,
+    shv_ris: Vec<ShvRI>,
+    subscribers: Vec<shvclient::clientapi::Subscriber>,
+    mut broker_connection: tokio::sync::watch::Receiver<BrokerConnectionState>,
+    policy: Option<Policy>,
+    event_log: SessionEventLog,
+    _finished_tx: tokio::sync::watch::Sender<()>,
+) {
+    let mut notifications = rocket::futures::stream::select_all(subscribers);
+    let mut connection_id = broker_connection.borrow().connection_id;
+    loop {
+        while let Some(frame) = notifications.next().await {
+            let event = match frame.to_rpcmesage() {
+                Err(e) => {
+                    warn!("Received invalid RPC frame in notification: {e}\nframe: {frame}");
+                    SessionStreamEvent::Error(e.to_string())
+                }
+                Ok(msg) if !notification_permitted(policy.as_ref(), &msg) => continue,
+                Ok(msg) => SessionStreamEvent::Signal(SubscribeEvent {
+                    path: msg.shv_path().map(String::from),
+                    signal: msg.method().map(String::from),
+                    param: msg.param().cloned(),
+                }),
+            };
+            event_log.push(event);
+        }
 
-    struct UnsubscribeNotifier(UnboundedSender<SessionEvent>);
-    impl Drop for UnsubscribeNotifier {
-        fn drop(&mut self) {
-            self.0.unbounded_send(SessionEvent::Unsubscription)
-                .unwrap_or_else(|e| error!("Cannot send SessionEvent::Unsubscription: {e}"));
+        // The subscriptions end when the client terminates or disconnects
+        // from the broker. In the latter case, wait for a reconnect and
+        // subscribe again.
+        if broker_connection
+            .wait_for(|state| !state.connected || state.connection_id != connection_id)
+            .await
+            .is_err() {
+            break;
         }
+        event_log.push(SessionStreamEvent::Disconnected);
+        let Ok(state) = broker_connection.wait_for(|state| state.connected).await.map(|state| *state) else {
+            break;
+        };
+        connection_id = state.connection_id;
+        match subscribe_all(&command_channel, &shv_ris).await {
+            Ok(subscribers) => notifications = rocket::futures::stream::select_all(subscribers),
+            Err(e) => {
+                event_log.push(SessionStreamEvent::Error(format!("Cannot subscribe after reconnect: {e}")));
+                break;
+            }
+        }
+        event_log.push(SessionStreamEvent::Reconnected);
+    }
+}
+
+async fn subscribe_notifications(
+    session: Session,
+    shv_ris: &[&str],
+    last_event_id: Option<SubscribeLastEventId>,
+    typed_json: bool,
+    policy: Option<Policy>,
+) -> Result<EventStream![], ErrorResponse>
+{
+    let Session(_session_id, SessionData { command_channel, session_channel, broker_connection, subscribe_streams, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let shv_ris = shv_ris
+        .iter()
+        .map(|shv_ri| ShvRI::try_from(*shv_ri))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| err_response(Status::UnprocessableEntity, e))?;
+    for shv_ri in &shv_ris {
+        check_policy(policy.as_ref(), shv_ri)?;
     }
+    let resumed = last_event_id.map(|SubscribeLastEventId { stream_id, event_id }|
+        (stream_id, event_id, subscribe_streams.resume(stream_id, &shv_ris))
+    );
+    let (stream, resumed_event_id, lost_stream_id) = match resumed {
+        Some((_, event_id, Some(stream))) => (stream, Some(event_id), None),
+        resumed => {
+            let stream = subscribe_streams
+                .start(&command_channel, shv_ris, broker_connection, policy)
+                .await
+                .map_err(|e| err_response(Status::InternalServerError, e.to_string()))?;
+            (stream, None, resumed.map(|(stream_id, _, _)| stream_id))
+        }
+    };
+    let stream_id = stream.stream_id;
+    let (replay, mut events_rx) = stream.event_log.subscribe(resumed_event_id);
+    let mut finished = stream.finished.clone();
+    let attached_stream = AttachedSubscribeStream { streams: subscribe_streams, stream: Some(stream) };
 
     session_channel
         .unbounded_send(SessionEvent::Subscription)
@@ -128,25 +423,33 @@ async fn api_subscribe(
     let event_stream = EventStream! {
         // Notify the session task when the EventStream finishes
         let _notifier = UnsubscribeNotifier(session_channel);
+        let _attached_stream = attached_stream;
+        if let Some(lost_stream_id) = lost_stream_id {
+            yield Event::data(format!("Stream {lost_stream_id} cannot be resumed, its notifications are lost")).event("error");
+        }
+        let SessionEventReplay { lost_count, events } = replay;
+        if lost_count > 0 {
+            yield Event::data(format!("{lost_count} notifications lost")).event("error");
+        }
+        for (event_id, event) in events {
+            yield event.into_subscribe_event(stream_id, event_id, typed_json);
+        }
         loop {
-            match subscriber.next().await {
-                None => break,
-                Some(frame) => {
-                    match frame.to_rpcmesage() {
-                        Err(e) => {
-                            warn!("Received invalid RPC frame in notification: {e}\nframe: {frame}");
-                            yield Event::data(e.to_string()).event("error");
-                        }
-                        Ok(msg) => yield Event::data(
-                            RpcValue::from(SubscribeEvent{
-                                path: msg.shv_path().map(String::from),
-                                signal: msg.method().map(String::from),
-                                param: msg.param().cloned(),
-                            })
-                            .to_json()
-                        ),
+            tokio::select! {
+                event = events_rx.recv() => match event {
+                    Ok((event_id, event)) => yield event.into_subscribe_event(stream_id, event_id, typed_json),
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(count)) => {
+                        warn!("Subscribe stream lagged, {count} notifications dropped");
+                        yield Event::data(format!("{count} notifications dropped")).event("error");
                     }
-
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+                _ = finished.changed() => {
+                    // Deliver the events pushed before the subscriptions ended
+                    while let Ok((event_id, event)) = events_rx.try_recv() {
+                        yield event.into_subscribe_event(stream_id, event_id, typed_json);
+                    }
+                    break;
                 }
             }
         }
@@ -154,6 +457,352 @@ async fn api_subscribe(
     Ok(event_stream)
 }
 
+/// Encodes a value for the `data` field of an SSE event
+fn sse_data(value: &RpcValue, typed_json: bool) -> Result<String, String> {
+    if typed_json {
+        to_typed_json(value).map(|json| json.to_string())
+    } else {
+        Ok(value.to_json())
+    }
+}
+
+/// Makes an SSE event with the encoded value, or an `error` event if the value
+/// cannot be encoded
+fn sse_value_event(value: &RpcValue, typed_json: bool, event: Option<&'static str>) -> Event {
+    match sse_data(value, typed_json) {
+        Ok(data) => match event {
+            Some(event) => Event::data(data).event(event),
+            None => Event::data(data),
+        },
+        Err(e) => Event::data(e).event("error"),
+    }
+}
+
+/// A notification delivered on the session notification stream, tagged with
+/// the ID of the subscription it matched.
+#[derive(Clone, shvproto::FromRpcValue, shvproto::ToRpcValue)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+struct SessionNotification {
+    #[field_name = "subscription_id"]
+    subscription_id: i64,
+    path: Option<String>,
+    signal: Option<String>,
+    param: Option<RpcValue>,
+}
+
+#[derive(Clone)]
+enum SessionStreamEvent {
+    Notification(SessionNotification),
+    /// A notification delivered on a subscribe stream
+    Signal(SubscribeEvent),
+    Error(String),
+    Disconnected,
+    Reconnected,
+}
+
+impl SessionStreamEvent {
+    fn into_sse_event(self, typed_json: bool) -> Event {
+        match self {
+            SessionStreamEvent::Notification(notification) => sse_value_event(&RpcValue::from(notification), typed_json, None),
+            SessionStreamEvent::Signal(signal) => sse_value_event(&RpcValue::from(signal), typed_json, None),
+            SessionStreamEvent::Error(msg) => Event::data(msg).event("error"),
+            SessionStreamEvent::Disconnected => Event::data("Connection to the broker lost").event("disconnected"),
+            SessionStreamEvent::Reconnected => Event::data("Connection to the broker restored").event("reconnected"),
+        }
+    }
+
+    fn into_event(self, event_id: u64, typed_json: bool) -> Event {
+        self.into_sse_event(typed_json).id(event_id.to_string())
+    }
+
+    fn into_subscribe_event(self, stream_id: u64, event_id: u64, typed_json: bool) -> Event {
+        self.into_sse_event(typed_json).id(format!("{stream_id}.{event_id}"))
+    }
+}
+
+struct SessionEventHistory {
+    last_event_id: u64,
+    events: std::collections::VecDeque<(u64, SessionStreamEvent)>,
+    capacity: usize,
+}
+
+/// Assigns IDs to the events of the session notification stream or of a
+/// subscribe stream and keeps the most recent ones for a replay to reconnecting
+/// clients.
+#[derive(Clone)]
+struct SessionEventLog {
+    history: Arc<std::sync::Mutex<SessionEventHistory>>,
+    events_tx: tokio::sync::broadcast::Sender<(u64, SessionStreamEvent)>,
+}
+
+struct SessionEventReplay {
+    /// Number of events that happened after the requested event ID and are no
+    /// longer in the history
+    lost_count: u64,
+    events: Vec<(u64, SessionStreamEvent)>,
+}
+
+impl SessionEventLog {
+    fn new(capacity: usize) -> Self {
+        Self {
+            history: Arc::new(std::sync::Mutex::new(SessionEventHistory {
+                last_event_id: 0,
+                events: Default::default(),
+                capacity,
+            })),
+            events_tx: tokio::sync::broadcast::channel(SESSION_NOTIFICATIONS_CAPACITY).0,
+        }
+    }
+
+    fn push(&self, event: SessionStreamEvent) {
+        // The history lock is held while sending to keep the order of the
+        // replayed and the live events.
+        let mut history = self.history.lock().expect("Session event history lock is not poisoned");
+        history.last_event_id += 1;
+        let event_id = history.last_event_id;
+        if history.capacity > 0 {
+            if history.events.len() == history.capacity {
+                history.events.pop_front();
+            }
+            history.events.push_back((event_id, event.clone()));
+        }
+        // An error means there is no stream opened at the moment
+        let _ = self.events_tx.send((event_id, event));
+    }
+
+    fn subscribe(&self, last_event_id: Option<u64>) -> (SessionEventReplay, tokio::sync::broadcast::Receiver<(u64, SessionStreamEvent)>) {
+        let history = self.history.lock().expect("Session event history lock is not poisoned");
+        let events_rx = self.events_tx.subscribe();
+        let replay = match last_event_id {
+            Some(last_event_id) if last_event_id < history.last_event_id => {
+                let first_kept_id = history.events.front().map_or(history.last_event_id + 1, |(id, _)| *id);
+                SessionEventReplay {
+                    lost_count: first_kept_id.saturating_sub(last_event_id + 1),
+                    events: history.events
+                        .iter()
+                        .filter(|(id, _)| *id > last_event_id)
+                        .cloned()
+                        .collect(),
+                }
+            }
+            _ => SessionEventReplay { lost_count: 0, events: vec![] },
+        };
+        (replay, events_rx)
+    }
+}
+
+struct SessionSubscription {
+    shv_ri: ShvRI,
+    // Owns the Subscriber, aborting the task unsubscribes
+    task: tokio::task::JoinHandle<()>,
+}
+
+/// Subscriptions of a session multiplexed to a single notification stream
+#[derive(Clone)]
+struct SessionNotifications {
+    subscriptions: Arc<Mutex<HashMap<i64, SessionSubscription>>>,
+    last_subscription_id: Arc<std::sync::atomic::AtomicI64>,
+    event_log: SessionEventLog,
+    policy: Option<Policy>,
+}
+
+const SESSION_NOTIFICATIONS_CAPACITY: usize = 1000;
+
+impl SessionNotifications {
+    fn new(history_size: usize, policy: Option<Policy>) -> Self {
+        Self {
+            subscriptions: Default::default(),
+            last_subscription_id: Default::default(),
+            event_log: SessionEventLog::new(history_size),
+            policy,
+        }
+    }
+
+    async fn subscribe(&self, command_channel: &ClientCommandSender, shv_ri: ShvRI) -> Result<i64, CallRpcMethodError> {
+        let subscriber = command_channel.subscribe(shv_ri.clone()).await?;
+        let subscription_id = self.last_subscription_id.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+        let task = self.spawn_forwarding_task(subscription_id, subscriber);
+        self.subscriptions.lock().await.insert(subscription_id, SessionSubscription { shv_ri, task });
+        Ok(subscription_id)
+    }
+
+    /// Subscribes all the subscriptions again after the client has reconnected
+    /// to the broker, which has dropped them.
+    ///
+    /// The lock is not held while subscribing, so that the subscriptions can
+    /// be listed, added and removed in the meantime.
+    async fn resubscribe(&self, command_channel: &ClientCommandSender) {
+        let shv_ris = self.subscriptions
+            .lock()
+            .await
+            .iter()
+            .map(|(subscription_id, subscription)| (*subscription_id, subscription.shv_ri.clone()))
+            .collect::<Vec<_>>();
+        for (subscription_id, shv_ri) in shv_ris {
+            let result = command_channel.subscribe(shv_ri.clone()).await;
+            let mut subscriptions = self.subscriptions.lock().await;
+            // Dropping the subscriber of a subscription removed in the meantime unsubscribes
+            let Some(subscription) = subscriptions.get_mut(&subscription_id) else {
+                continue;
+            };
+            match result {
+                Ok(subscriber) => {
+                    subscription.task.abort();
+                    subscription.task = self.spawn_forwarding_task(subscription_id, subscriber);
+                }
+                Err(e) => {
+                    warn!("Cannot resubscribe `{shv_ri}`: {e}");
+                    self.event_log.push(SessionStreamEvent::Error(
+                        format!("Subscription {subscription_id} removed, cannot resubscribe: {e}")
+                    ));
+                    subscriptions.remove(&subscription_id);
+                }
+            }
+        }
+    }
+
+    fn spawn_forwarding_task(&self, subscription_id: i64, mut subscriber: shvclient::clientapi::Subscriber) -> tokio::task::JoinHandle<()> {
+        let event_log = self.event_log.clone();
+        let policy = self.policy.clone();
+        tokio::spawn(async move {
+            while let Some(frame) = subscriber.next().await {
+                let event = match frame.to_rpcmesage() {
+                    Err(e) => {
+                        warn!("Received invalid RPC frame in notification: {e}\nframe: {frame}");
+                        SessionStreamEvent::Error(e.to_string())
+                    }
+                    Ok(msg) if !notification_permitted(policy.as_ref(), &msg) => continue,
+                    Ok(msg) => SessionStreamEvent::Notification(SessionNotification {
+                        subscription_id,
+                        path: msg.shv_path().map(String::from),
+                        signal: msg.method().map(String::from),
+                        param: msg.param().cloned(),
+                    }),
+                };
+                event_log.push(event);
+            }
+        })
+    }
+
+    async fn unsubscribe(&self, subscription_id: i64) -> bool {
+        self.subscriptions
+            .lock()
+            .await
+            .remove(&subscription_id)
+            .map(|SessionSubscription { task, .. }| task.abort())
+            .is_some()
+    }
+
+    async fn list(&self) -> Vec<SubscriptionInfo> {
+        let mut list = self.subscriptions
+            .lock()
+            .await
+            .iter()
+            .map(|(subscription_id, SessionSubscription { shv_ri, .. })| SubscriptionInfo {
+                subscription_id: *subscription_id,
+                shv_ri: shv_ri.to_string(),
+            })
+            .collect::<Vec<_>>();
+        list.sort_by_key(|info| info.subscription_id);
+        list
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+struct SubscriptionInfo {
+    subscription_id: i64,
+    shv_ri: String,
+}
+
+/// The value of the `Last-Event-ID` header sent by reconnecting `EventSource` clients
+struct LastEventId(u64);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
+        use rocket::request::Outcome;
+        match req.headers().get_one("Last-Event-ID").map(str::parse) {
+            Some(Ok(event_id)) => Outcome::Success(LastEventId(event_id)),
+            Some(Err(_)) => Outcome::Error((Status::BadRequest, ())),
+            None => Outcome::Forward(Status::Ok),
+        }
+    }
+}
+
+#[get("/notifications?<typed_json>")]
+async fn api_notifications(session: EventSourceSession, last_event_id: Option<LastEventId>, typed_json: Option<bool>) -> EventStream![] {
+    let typed_json = typed_json.unwrap_or(false);
+    let EventSourceSession(Session(_session_id, SessionData { session_channel, notifications, .. })) = session;
+    let (replay, mut events_rx) = notifications.event_log.subscribe(last_event_id.map(|LastEventId(id)| id));
+    drop(notifications);
+
+    session_channel
+        .unbounded_send(SessionEvent::Subscription)
+        .unwrap_or_else(|e| error!("Cannot send SessionEvent::Subscription: {e}"));
+
+    EventStream! {
+        // Notify the session task when the EventStream finishes
+        let _notifier = UnsubscribeNotifier(session_channel);
+        let SessionEventReplay { lost_count, events } = replay;
+        if lost_count > 0 {
+            yield Event::data(format!("{lost_count} notifications lost")).event("error");
+        }
+        for (event_id, event) in events {
+            yield event.into_event(event_id, typed_json);
+        }
+        loop {
+            match events_rx.recv().await {
+                Ok((event_id, event)) => yield event.into_event(event_id, typed_json),
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(count)) => {
+                    warn!("Notification stream lagged, {count} notifications dropped");
+                    yield Event::data(format!("{count} notifications dropped")).event("error");
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+#[get("/subscriptions")]
+async fn api_subscriptions(session: Session) -> Json<Vec<SubscriptionInfo>> {
+    let Session(_, SessionData { notifications, .. }) = session;
+    Json(notifications.list().await)
+}
+
+#[post("/subscriptions", data = "<request>")]
+async fn api_subscriptions_add(
+    session: Session,
+    request: Result<Json<SubscribeRequest<'_>>, rocket::serde::json::Error<'_>>,
+    program_config: &State<Arc<ProgramConfig>>,
+) -> Result<Json<SubscriptionInfo>, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, notifications, broker_connection, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let Json(SubscribeRequest { shv_ri }) = request
+        .map_err(|e| err_response(Status::UnprocessableEntity, e.to_string()))?;
+    let shv_ri = ShvRI::try_from(shv_ri)
+        .map_err(|e| err_response(Status::UnprocessableEntity, e))?;
+    check_policy(program_config.policy.as_ref(), &shv_ri)?;
+    let subscription_id = notifications
+        .subscribe(&command_channel, shv_ri.clone())
+        .await
+        .map_err(|e| err_response(Status::InternalServerError, e.to_string()))?;
+    Ok(Json(SubscriptionInfo { subscription_id, shv_ri: shv_ri.to_string() }))
+}
+
+#[delete("/subscriptions/<subscription_id>")]
+async fn api_subscriptions_remove(session: Session, subscription_id: i64) -> Result<(), ErrorResponse> {
+    let Session(_, SessionData { notifications, .. }) = session;
+    if notifications.unsubscribe(subscription_id).await {
+        Ok(())
+    } else {
+        Err(err_response(Status::NotFound, format!("Subscription {subscription_id} not found")))
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug, PartialEq))]
 struct LoginResponse {
@@ -163,12 +812,12 @@ struct LoginResponse {
 #[post("/login", data = "<params>")]
 async fn api_login(
     params: Result<Json<LoginParams<'_>>, rocket::serde::json::Error<'_>>,
-    program_config: &State<ProgramConfig>,
+    program_config: &State<Arc<ProgramConfig>>,
     sessions: &State<Sessions>,
     random: &State<Random>,
 ) -> Result<Json<LoginResponse>, ErrorResponse>
 {
-    let params = params
+    let Json(params) = params
         .map_err(|e| err_response(Status::UnprocessableEntity, e.to_string()))?;
     let mut url = program_config.broker_url.clone();
     url.set_username(params.username)
@@ -182,9 +831,38 @@ async fn api_login(
             err_response(Status::InternalServerError, "Cannot authenticate")
         })?;
     let heartbeat_interval = program_config.heartbeat_interval;
-    let client_config = shvrpc::client::ClientConfig { url, heartbeat_interval, ..Default::default() };
+    let reconnect_interval = program_config.reconnect_interval;
+    let mut client_config = shvrpc::client::ClientConfig { url, heartbeat_interval, reconnect_interval, ..Default::default() };
+
+    let device = params.device
+        .map(|DeviceParams { mount, device_id, methods }| {
+            if program_config.read_only {
+                return Err(err_response(Status::Forbidden, "The gateway is read-only and the session cannot be mounted as a device"));
+            }
+            if mount.is_none() && device_id.is_none() {
+                return Err(err_response(Status::UnprocessableEntity, "The device requires a `mount` or a `device_id`"));
+            }
+            let methods = methods
+                .into_iter()
+                .map(MetaMethod::try_from)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| err_response(Status::UnprocessableEntity, e))?;
+            // The mount point of a device ID is known only after the login
+            if let Some(mount) = &mount {
+                check_device_policy(program_config, mount, &methods)?;
+            }
+            client_config.mount = mount;
+            client_config.device_id = device_id;
+            Ok(DeviceNode {
+                methods: methods.into(),
+                requests: DeviceRequests::new(program_config.device_requests_capacity),
+                request_timeout: program_config.device_request_timeout,
+            })
+        })
+        .transpose()?;
+    let device_requests = device.as_ref().map(|device| (device.methods.clone(), device.requests.clone()));
 
-    let (client_commands_tx, mut client_events_rx) = start_client(client_config)
+    let (client_commands_tx, mut client_events_rx) = start_client(client_config, device)
         .await
         .ok_or_else(|| {
             warn!("Cannot start SHV client for user `{}`", params.username);
@@ -192,16 +870,34 @@ async fn api_login(
         })?;
 
     // Wait for the client to connect
+    let connection_failed = |status, detail| {
+        if reconnect_interval.is_some() {
+            // The client would keep reconnecting otherwise
+            client_commands_tx.terminate_client();
+        }
+        err_response(status, detail)
+    };
     match client_events_rx.next().await {
         Some(ClientEvent::Connected(_)) => { }
         None | Some(ClientEvent::Disconnected) | Some(ClientEvent::ConnectionFailed(ConnectionFailedKind::NetworkError)) => {
-            return Err(err_response(Status::ServiceUnavailable, "Connection to the broker failed"));
+            return Err(connection_failed(Status::ServiceUnavailable, "Connection to the broker failed"));
         }
         Some(ClientEvent::ConnectionFailed(ConnectionFailedKind::LoginFailed)) => {
-            return Err(err_response(Status::Unauthorized, "Bad credentials"));
+            return Err(connection_failed(Status::Unauthorized, "Bad credentials"));
         }
     }
 
+    let device = match device_requests {
+        Some((methods, requests)) => match device_mount_point(&client_commands_tx, &methods, program_config).await {
+            Ok(mount_point) => Some(SessionDevice { mount_point, requests }),
+            Err(e) => {
+                client_commands_tx.terminate_client();
+                return Err(e);
+            }
+        },
+        None => None,
+    };
+
     // Generate a new session ID
     let Random(random) = random.inner();
     let mut random_bytes = vec![0u8;30];
@@ -226,17 +922,29 @@ async fn api_login(
         return Err(err_response(Status::Forbidden, "Maximum number of sessions for the user exceeded"));
     }
     let (session_tx, mut session_rx) = channel::mpsc::unbounded();
+    let (broker_connection_tx, broker_connection_rx) = tokio::sync::watch::channel(
+        BrokerConnectionState { connected: true, connection_id: 0 }
+    );
+    let notifications = SessionNotifications::new(program_config.notification_history_size, program_config.policy.clone());
+    let dir_cache = DirCache::new(program_config.dir_cache_ttl);
     // Save the session
     sessions_wr.insert(
         session_id.clone(),
         SessionData {
-            command_channel: client_commands_tx,
+            command_channel: client_commands_tx.clone(),
             session_channel: session_tx,
             username: params.username.into(),
+            notifications: notifications.clone(),
+            subscribe_streams: SubscribeStreams::new(program_config.subscribe_resume_timeout, program_config.notification_history_size),
+            broker_connection: broker_connection_rx,
+            rpc_calls: CancellableRpcCalls::default(),
+            dir_cache: dir_cache.clone(),
+            jobs: SessionJobs::new(program_config.job_retention),
+            device,
         });
     drop(sessions_wr);
 
-    // Spawn the session task, which maintains the timeout and removes the session when the client terminates
+    // Spawn the session task, which maintains the timeout, tracks the broker connection and removes the session when the client terminates
     {
         let session_timeout = program_config.session_timeout;
         let new_session_timer = move || Box::pin(Either::Left(tokio::time::sleep(session_timeout)));
@@ -247,6 +955,7 @@ async fn api_login(
         tokio::spawn(async move {
             let mut session_timer = new_session_timer();
             let mut subscriptions_count = 0_i64;
+            let mut running_jobs_count = 0_i64;
             loop {
                 tokio::select! {
                     _ = &mut session_timer => {
@@ -258,9 +967,39 @@ async fn api_login(
                         session_timer = disabled_session_timer();
                     }
                     client_event = client_events_rx.next() => match client_event {
-                        Some(ClientEvent::Connected(_)) => continue,
-                        _ => {
-                            if let Some(SessionData { username, .. }) = sessions.write().await.remove(&session_id) {
+                        Some(ClientEvent::Connected(_)) => {
+                            if broker_connection_tx.borrow().connected {
+                                continue;
+                            }
+                            info!("Session {session_id} has reconnected to the broker");
+                            dir_cache.clear();
+                            broker_connection_tx.send_modify(|state| {
+                                state.connected = true;
+                                state.connection_id += 1;
+                            });
+                            let notifications = notifications.clone();
+                            let client_commands_tx = client_commands_tx.clone();
+                            tokio::spawn(async move {
+                                notifications.resubscribe(&client_commands_tx).await;
+                                notifications.event_log.push(SessionStreamEvent::Reconnected);
+                            });
+                        }
+                        Some(ClientEvent::Disconnected | ClientEvent::ConnectionFailed(ConnectionFailedKind::NetworkError))
+                            if reconnect_interval.is_some() => {
+                            if !broker_connection_tx.borrow().connected {
+                                continue;
+                            }
+                            info!("Session {session_id} has disconnected from the broker, reconnecting");
+                            broker_connection_tx.send_modify(|state| state.connected = false);
+                            notifications.event_log.push(SessionStreamEvent::Disconnected);
+                        }
+                        client_event => {
+                            if let Some(SessionData { command_channel, username, jobs, .. }) = sessions.write().await.remove(&session_id) {
+                                jobs.cancel_all();
+                                if reconnect_interval.is_some() && client_event.is_some() {
+                                    // Stop reconnecting, e.g. after a login failure
+                                    command_channel.terminate_client();
+                                }
                                 info!("Session {session_id} for user {username} has been removed");
                             }
                             break;
@@ -268,15 +1007,15 @@ async fn api_login(
                     },
                     session_event = &mut session_rx.select_next_some() => match session_event {
                         SessionEvent::Activity => {
-                            // Reset the timer unless there is an active subscription, in which
-                            // case the timer is disabled.
-                            if subscriptions_count == 0 {
+                            // Reset the timer unless there is an active subscription or a running
+                            // job, in which case the timer is disabled.
+                            if subscriptions_count + running_jobs_count == 0 {
                                 session_timer = new_session_timer();
                             }
-                            debug!("activity, subscriptions count: {subscriptions_count}");
+                            debug!("activity, subscriptions count: {subscriptions_count}, running jobs count: {running_jobs_count}");
                         }
                         SessionEvent::Subscription => {
-                            if subscriptions_count == 0 {
+                            if subscriptions_count + running_jobs_count == 0 {
                                 session_timer = disabled_session_timer();
                             }
                             subscriptions_count += 1;
@@ -284,11 +1023,25 @@ async fn api_login(
                         },
                         SessionEvent::Unsubscription => {
                             subscriptions_count -= 1;
-                            if subscriptions_count == 0 {
+                            if subscriptions_count + running_jobs_count == 0 {
                                 session_timer = new_session_timer();
                             }
                             debug!("-subscription: {subscriptions_count}");
                         },
+                        SessionEvent::JobStarted => {
+                            if subscriptions_count + running_jobs_count == 0 {
+                                session_timer = disabled_session_timer();
+                            }
+                            running_jobs_count += 1;
+                            debug!("+job: {running_jobs_count}");
+                        },
+                        SessionEvent::JobFinished => {
+                            running_jobs_count -= 1;
+                            if subscriptions_count + running_jobs_count == 0 {
+                                session_timer = new_session_timer();
+                            }
+                            debug!("-job: {running_jobs_count}");
+                        },
                     }
                 }
             }
@@ -302,12 +1055,76 @@ async fn api_login(
 struct LoginParams<'r> {
     username: &'r str,
     password: &'r str,
+    device: Option<DeviceParams>,
+}
+
+/// Mounts the session as an SHV device exposing the declared methods
+#[derive(Deserialize)]
+struct DeviceParams {
+    mount: Option<String>,
+    device_id: Option<String>,
+    #[serde(default)]
+    methods: Vec<DeviceMethodParams>,
+}
+
+/// Method declared by a session mounted as a device, in the format of `GET /api/dir`
+#[derive(Deserialize)]
+struct DeviceMethodParams {
+    name: String,
+    #[serde(default)]
+    flags: Vec<String>,
+    #[serde(default)]
+    access: Option<String>,
+    #[serde(default)]
+    param: String,
+    #[serde(default)]
+    result: String,
+    #[serde(default)]
+    signals: BTreeMap<String, Option<String>>,
+}
+
+impl TryFrom<DeviceMethodParams> for MetaMethod {
+    type Error = String;
+
+    fn try_from(method: DeviceMethodParams) -> Result<Self, Self::Error> {
+        let DeviceMethodParams { name, flags, access, param, result, signals } = method;
+        if name.is_empty() || name == "dir" || name == "ls" {
+            return Err(format!("Invalid method name `{name}`"));
+        }
+        let flags = flags
+            .iter()
+            .map(|flag| Flags::from_name(flag).ok_or_else(|| format!("Invalid flag `{flag}` of method `{name}`")))
+            .collect::<Result<Flags, _>>()?;
+        let access = match access {
+            Some(access) => AccessLevel::from_str(&access)
+                .ok_or_else(|| format!("Invalid access level `{access}` of method `{name}`"))?,
+            None => AccessLevel::Read,
+        };
+        Ok(MetaMethod {
+            name: name.into(),
+            flags,
+            access,
+            param: param.into(),
+            result: result.into(),
+            signals: SignalsDefinition::Dynamic(signals),
+            description: Default::default(),
+        })
+    }
 }
 
 enum SessionEvent {
     Activity,
     Subscription,
     Unsubscription,
+    JobStarted,
+    JobFinished,
+}
+
+#[derive(Clone, Copy)]
+struct BrokerConnectionState {
+    connected: bool,
+    /// Incremented on every reconnect
+    connection_id: u64,
 }
 
 #[derive(Clone)]
@@ -315,11 +1132,117 @@ struct SessionData {
     command_channel: ClientCommandSender,
     session_channel: UnboundedSender<SessionEvent>,
     username: String,
+    notifications: SessionNotifications,
+    subscribe_streams: SubscribeStreams,
+    broker_connection: tokio::sync::watch::Receiver<BrokerConnectionState>,
+    rpc_calls: CancellableRpcCalls,
+    dir_cache: DirCache,
+    jobs: SessionJobs,
+    /// The mount point and the requests of a session mounted as a device
+    device: Option<SessionDevice>,
 }
 
+/// RPC calls in progress, which were given an ID by the client and can be cancelled
 #[derive(Clone, Default)]
-struct Sessions(pub(crate) Arc<RwLock<HashMap<String, SessionData>>>);
-
+struct CancellableRpcCalls(Arc<std::sync::Mutex<HashMap<String, tokio::sync::oneshot::Sender<()>>>>);
+
+impl CancellableRpcCalls {
+    /// Returns `None` if a call with the same ID is already in progress
+    fn start(&self, call_id: &str) -> Option<CancellableRpcCall> {
+        let mut calls = self.0.lock().expect("Cancellable RPC calls lock is not poisoned");
+        if calls.contains_key(call_id) {
+            return None;
+        }
+        let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
+        calls.insert(call_id.into(), cancel_tx);
+        Some(CancellableRpcCall { calls: self.clone(), call_id: call_id.into(), cancel_rx })
+    }
+
+    fn cancel(&self, call_id: &str) -> bool {
+        self.0
+            .lock()
+            .expect("Cancellable RPC calls lock is not poisoned")
+            .remove(call_id)
+            .is_some_and(|cancel_tx| cancel_tx.send(()).is_ok())
+    }
+}
+
+/// Unregisters the call from `CancellableRpcCalls` when dropped
+struct CancellableRpcCall {
+    calls: CancellableRpcCalls,
+    call_id: String,
+    cancel_rx: tokio::sync::oneshot::Receiver<()>,
+}
+
+impl Drop for CancellableRpcCall {
+    fn drop(&mut self) {
+        self.cancel_rx.close();
+        let mut calls = self.calls.0.lock().expect("Cancellable RPC calls lock is not poisoned");
+        // The ID could have been reused by another call after a cancellation
+        if calls.get(&self.call_id).is_some_and(tokio::sync::oneshot::Sender::is_closed) {
+            calls.remove(&self.call_id);
+        }
+    }
+}
+
+/// Method descriptors of a path returned by `dir` with the time of the call
+type DirCacheEntry = (std::time::Instant, Arc<Vec<MethodInfo>>);
+
+/// Method descriptors of SHV paths returned by `dir`, cached for the session
+#[derive(Clone)]
+struct DirCache {
+    entries: Arc<std::sync::Mutex<HashMap<String, DirCacheEntry>>>,
+    ttl: Duration,
+}
+
+impl DirCache {
+    fn new(ttl: Duration) -> Self {
+        Self { entries: Default::default(), ttl }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, DirCacheEntry>> {
+        self.entries.lock().expect("Dir cache lock is not poisoned")
+    }
+
+    /// Drops all the entries, e.g. when the devices may have changed after a
+    /// reconnect to the broker
+    fn clear(&self) {
+        self.lock().clear();
+    }
+
+    async fn methods(
+        &self,
+        command_channel: &ClientCommandSender,
+        path: &str,
+        timeout: Option<Duration>,
+        program_config: &ProgramConfig,
+    ) -> Result<Arc<Vec<MethodInfo>>, ErrorResponse>
+    {
+        if let Some((cached_at, methods)) = self.lock().get(path)
+            && cached_at.elapsed() < self.ttl {
+            return Ok(methods.clone());
+        }
+        let methods = command_channel
+            .call_dir_brief(path, timeout.map(Into::into), None)
+            .await
+            .map_err(|e| err_response_rpc_call(e, program_config))?;
+        let methods = Arc::new(methods);
+        self.lock().insert(path.into(), (std::time::Instant::now(), methods.clone()));
+        Ok(methods)
+    }
+}
+
+fn check_broker_connection(broker_connection: &tokio::sync::watch::Receiver<BrokerConnectionState>) -> Result<(), ErrorResponse> {
+    if broker_connection.borrow().connected {
+        Ok(())
+    } else {
+        Err(err_response(Status::ServiceUnavailable, "The broker is unreachable, reconnecting"))
+    }
+}
+
+#[derive(Clone, Default)]
+struct Sessions(pub(crate) Arc<RwLock<HashMap<String, SessionData>>>);
+
 
 #[post("/logout")]
 async fn api_logout(session: Session) {
@@ -333,19 +1256,118 @@ struct RpcRequest {
     path: String,
     method: String,
     param: Option<RpcValue>,
+    timeout: Option<String>,
+}
+
+/// The names returned by `rpc_error_name`, checked by the `rpc_error_names` test
+const RPC_ERROR_NAMES: &[&str] = &[
+    "ConnectionClosed",
+    "InvalidMessage",
+    "ResultTypeMismatch",
+    "NoError",
+    "InvalidRequest",
+    "MethodNotFound",
+    "InvalidParam",
+    "InternalError",
+    "ParseError",
+    "MethodCallTimeout",
+    "MethodCallCancelled",
+    "MethodCallException",
+    "PermissionDenied",
+    "LoginRequired",
+    "UserIDRequired",
+    "NotImplemented",
+    "TryAgainLater",
+    "AbortRequestInvalid",
+    "UserError",
+];
+
+fn rpc_error_name(error: &CallRpcMethodErrorKind) -> String {
+    match error {
+        CallRpcMethodErrorKind::ConnectionClosed => "ConnectionClosed".to_string(),
+        CallRpcMethodErrorKind::InvalidMessage(_) => "InvalidMessage".to_string(),
+        CallRpcMethodErrorKind::ResultTypeMismatch(_) => "ResultTypeMismatch".to_string(),
+        CallRpcMethodErrorKind::RpcError(rpc_err) => match rpc_err.code {
+            RpcErrorCodeKind::RpcError(code) => code.to_string(),
+            RpcErrorCodeKind::UserError(_) => "UserError".to_string(),
+        },
+    }
+}
+
+fn default_rpc_error_status(error: &CallRpcMethodErrorKind) -> Status {
+    match error {
+        CallRpcMethodErrorKind::ConnectionClosed => Status::ServiceUnavailable,
+        CallRpcMethodErrorKind::InvalidMessage(_) | CallRpcMethodErrorKind::ResultTypeMismatch(_) => Status::BadGateway,
+        CallRpcMethodErrorKind::RpcError(rpc_err) => match rpc_err.code {
+            RpcErrorCodeKind::RpcError(code) => match code {
+                RpcErrorCode::InvalidRequest
+                    | RpcErrorCode::InvalidParam
+                    | RpcErrorCode::ParseError
+                    | RpcErrorCode::AbortRequestInvalid => Status::BadRequest,
+                RpcErrorCode::MethodNotFound => Status::NotFound,
+                RpcErrorCode::PermissionDenied
+                    | RpcErrorCode::LoginRequired
+                    | RpcErrorCode::UserIDRequired => Status::Forbidden,
+                RpcErrorCode::MethodCallTimeout => Status::GatewayTimeout,
+                RpcErrorCode::NotImplemented => Status::NotImplemented,
+                RpcErrorCode::TryAgainLater => Status::ServiceUnavailable,
+                RpcErrorCode::NoError
+                    | RpcErrorCode::InternalError
+                    | RpcErrorCode::MethodCallCancelled
+                    | RpcErrorCode::MethodCallException => Status::InternalServerError,
+            },
+            RpcErrorCodeKind::UserError(_) => Status::InternalServerError,
+        },
+    }
+}
+
+#[derive(Clone, Debug)]
+struct RpcErrorStatusOverride {
+    error: String,
+    status: Status,
+}
+
+fn parse_rpc_error_status_override(val: &str) -> Result<RpcErrorStatusOverride, String> {
+    let (error, status) = val
+        .split_once('=')
+        .ok_or_else(|| "Expected <SHV_ERROR>=<HTTP_STATUS>".to_string())?;
+    if !RPC_ERROR_NAMES.contains(&error) {
+        return Err(format!("Unknown SHV error `{error}`, expected one of: {}", RPC_ERROR_NAMES.join(", ")));
+    }
+    let status = status
+        .parse()
+        .ok()
+        .and_then(Status::from_code)
+        .filter(|status| status.code >= 400)
+        .ok_or_else(|| format!("Invalid HTTP error status `{status}`"))?;
+    Ok(RpcErrorStatusOverride { error: error.into(), status })
+}
+
+fn rpc_error_status(error: &CallRpcMethodErrorKind, program_config: &ProgramConfig) -> Status {
+    if program_config.rpc_error_status_compat {
+        return Status::InternalServerError;
+    }
+    let error_name = rpc_error_name(error);
+    program_config.rpc_error_status
+        .iter()
+        .rev()
+        .find(|status_override| status_override.error == error_name)
+        .map_or_else(|| default_rpc_error_status(error), |status_override| status_override.status)
 }
 
-fn err_response_rpc_call(e: CallRpcMethodError) -> ErrorResponse {
+fn err_response_rpc_call(e: CallRpcMethodError, program_config: &ProgramConfig) -> ErrorResponse {
+    let status = rpc_error_status(e.error(), program_config);
     (
-        Status::InternalServerError,
+        status,
         Json(ErrorResponseBody {
-            code: Status::InternalServerError.code,
+            code: status.code,
             shv_error: Some(match e.error() {
                 CallRpcMethodErrorKind::ConnectionClosed => "ConnectionClosed".to_string(),
                 CallRpcMethodErrorKind::InvalidMessage(_) => "InvalidMessage".to_string(),
                 CallRpcMethodErrorKind::RpcError(rpc_err) => format!("RpcError({})", rpc_err.code),
                 CallRpcMethodErrorKind::ResultTypeMismatch(_) => "ResultTypeMismatch".to_string(),
             }),
+            rpc_error: Some(Box::new(RpcErrorInfo::from(&e))),
             detail: e.to_string(),
         })
     )
@@ -361,10 +1383,269 @@ macro_rules !return_err {
     };
 }
 
-struct RpcValueJson<T>(T);
+/// Encodings of `RpcValue` in request and response bodies
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RpcValueFormat {
+    Json,
+    TypedJson,
+    Cpon,
+    ChainPack,
+}
+
+impl RpcValueFormat {
+    fn from_media_type(media_type: &rocket::http::MediaType) -> Option<Self> {
+        if media_type.top() != "application" {
+            return None;
+        }
+        match media_type.sub().as_str() {
+            "json" => Some(Self::Json),
+            "x-typed-json" => Some(Self::TypedJson),
+            "x-cpon" => Some(Self::Cpon),
+            "x-chainpack" => Some(Self::ChainPack),
+            _ => None,
+        }
+    }
+
+    /// Picks the format with the highest weight in the `Accept` header, JSON by default
+    fn accepted(req: &Request<'_>) -> Self {
+        let Some(accept) = req.accept() else {
+            return Self::Json;
+        };
+        let mut accepted: Option<(Self, f32)> = None;
+        for media_type in accept.iter() {
+            let weight = media_type.weight_or(1.0);
+            if let Some(format) = Self::from_media_type(media_type.media_type())
+                && accepted.is_none_or(|(_, accepted_weight)| weight > accepted_weight) {
+                accepted = Some((format, weight));
+            }
+        }
+        accepted.map_or(Self::Json, |(format, _)| format)
+    }
+
+    fn content_type(self) -> rocket::http::ContentType {
+        match self {
+            Self::Json => rocket::http::ContentType::JSON,
+            Self::TypedJson => rocket::http::ContentType::new("application", "x-typed-json"),
+            Self::Cpon => rocket::http::ContentType::new("application", "x-cpon"),
+            Self::ChainPack => rocket::http::ContentType::new("application", "x-chainpack"),
+        }
+    }
+
+    fn encode(self, value: &RpcValue) -> Result<Vec<u8>, String> {
+        Ok(match self {
+            Self::Json => value.to_json().into_bytes(),
+            Self::TypedJson => to_typed_json(value)?.to_string().into_bytes(),
+            Self::Cpon => value.to_cpon().into_bytes(),
+            Self::ChainPack => value.to_chainpack(),
+        })
+    }
+
+    fn decode(self, data: &[u8]) -> Result<RpcValue, String> {
+        let text = || std::str::from_utf8(data).map_err(|e| format!("Invalid UTF-8: {e}"));
+        match self {
+            Self::Json => RpcValue::from_json(text()?).map_err(|e| e.to_string()),
+            Self::TypedJson => serde_json::from_slice(data)
+                .map_err(|e| e.to_string())
+                .and_then(|json| from_typed_json(&json)),
+            Self::Cpon => RpcValue::from_cpon(text()?).map_err(|e| e.to_string()),
+            Self::ChainPack => RpcValue::from_chainpack(data).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for RpcValueFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Json => "JSON",
+            Self::TypedJson => "typed JSON",
+            Self::Cpon => "CPON",
+            Self::ChainPack => "ChainPack",
+        })
+    }
+}
+
+const TYPED_JSON_TYPE: &str = "$type";
+const TYPED_JSON_META: &str = "$meta";
+const TYPED_JSON_VALUE: &str = "value";
+
+/// Converts an `RpcValue` to JSON without losing its type and meta.
+///
+/// Null, Bool, Int, finite Double, String, List and Map values are plain JSON.
+/// Other values, values with meta and maps with a `$type` or `$meta` key are
+/// wrapped in an envelope `{"$type": <type name>, "$meta": {...}, "value": ...}`.
+/// The value of a Decimal is `{"mantissa": ..., "exponent": ...}`, DateTime is
+/// an ISO string, Blob is a base64 string and the keys of IMap and meta are
+/// decimal strings for integer keys.
+fn to_typed_json(value: &RpcValue) -> Result<serde_json::Value, String> {
+    use serde_json::Value as JsonValue;
+    let json_value = match &value.value {
+        Value::Null => JsonValue::Null,
+        Value::Bool(b) => JsonValue::from(*b),
+        Value::Int(n) => JsonValue::from(*n),
+        Value::UInt(n) => JsonValue::from(*n),
+        Value::Double(n) if n.is_nan() => JsonValue::from("NaN"),
+        Value::Double(n) if n.is_infinite() => JsonValue::from(if n.is_sign_positive() { "Infinity" } else { "-Infinity" }),
+        Value::Double(n) => JsonValue::from(*n),
+        Value::Decimal(d) => serde_json::json!({ "mantissa": d.mantissa(), "exponent": d.exponent() }),
+        Value::DateTime(dt) => JsonValue::from(dt.to_iso_string()),
+        Value::String(s) => JsonValue::from(s.as_str()),
+        Value::Blob(b) => JsonValue::from(BASE64_STANDARD.encode(b.as_slice())),
+        Value::List(list) => list.iter().map(to_typed_json).collect::<Result<_, _>>()?,
+        Value::Map(map) => map
+            .iter()
+            .map(|(key, val)| Ok((key.clone(), to_typed_json(val)?)))
+            .collect::<Result<serde_json::Map<_, _>, String>>()?
+            .into(),
+        Value::IMap(imap) => imap
+            .iter()
+            .map(|(key, val)| Ok((key.to_string(), to_typed_json(val)?)))
+            .collect::<Result<serde_json::Map<_, _>, String>>()?
+            .into(),
+    };
+    let meta = value.meta.as_deref().filter(|meta| !meta.is_empty());
+    let is_plain = match &value.value {
+        Value::Null | Value::Bool(_) | Value::Int(_) | Value::String(_) | Value::List(_) => true,
+        Value::Double(n) => n.is_finite(),
+        Value::Map(map) => !map.contains_key(TYPED_JSON_TYPE) && !map.contains_key(TYPED_JSON_META),
+        _ => false,
+    };
+    if is_plain && meta.is_none() {
+        return Ok(json_value);
+    }
+    let mut envelope = serde_json::Map::new();
+    envelope.insert(TYPED_JSON_TYPE.into(), value.type_name().into());
+    if let Some(meta) = meta {
+        let meta = meta_entries(meta)?
+            .into_iter()
+            .map(|(key, val)| Ok((if key.is_int() { key.as_int().to_string() } else { key.as_str().to_string() }, to_typed_json(&val)?)))
+            .collect::<Result<serde_json::Map<_, _>, String>>()?;
+        envelope.insert(TYPED_JSON_META.into(), meta.into());
+    }
+    envelope.insert(TYPED_JSON_VALUE.into(), json_value);
+    Ok(envelope.into())
+}
+
+/// Returns the keys and values of a `MetaMap`, which does not provide an iterator.
+///
+/// The entries are read back from the ChainPack of the map, which keeps the
+/// types of the keys, i.e. a String key `"1"` is not read as an Int key.
+fn meta_entries(meta: &MetaMap) -> Result<Vec<(RpcValue, RpcValue)>, String> {
+    use shvproto::Reader;
+    let invalid_meta = |e: shvproto::ReadError| format!("Cannot read the meta entries: {e}");
+    let chainpack = RpcValue::new(Value::Null, Some(meta.clone())).to_chainpack();
+    let mut data = chainpack.as_slice();
+    let mut reader = shvproto::ChainPackReader::new(&mut data);
+    let mut entries = vec![];
+    // Skip the MetaMap schema
+    reader.read_schema().map_err(invalid_meta)?;
+    while !reader.is_container_end().map_err(invalid_meta)? {
+        let key = reader.read().map_err(invalid_meta)?;
+        let val = reader.read().map_err(invalid_meta)?;
+        entries.push((key, val));
+    }
+    Ok(entries)
+}
+
+/// Converts JSON in the format produced by `to_typed_json` to an `RpcValue`.
+/// Plain JSON numbers are Int, UInt for integers out of the Int range, or Double.
+fn from_typed_json(json: &serde_json::Value) -> Result<RpcValue, String> {
+    use serde_json::Value as JsonValue;
+    match json {
+        JsonValue::Null => Ok(RpcValue::null()),
+        JsonValue::Bool(b) => Ok(RpcValue::from(*b)),
+        JsonValue::Number(n) => n
+            .as_i64()
+            .map(RpcValue::from)
+            .or_else(|| n.as_u64().map(RpcValue::from))
+            .or_else(|| n.as_f64().map(RpcValue::from))
+            .ok_or_else(|| format!("Unsupported number: {n}")),
+        JsonValue::String(s) => Ok(RpcValue::from(s.as_str())),
+        JsonValue::Array(list) => typed_json_list(list).map(RpcValue::from),
+        JsonValue::Object(obj) if obj.contains_key(TYPED_JSON_TYPE) => from_typed_json_envelope(obj),
+        JsonValue::Object(obj) if obj.contains_key(TYPED_JSON_META) => Err(format!("`{TYPED_JSON_META}` without `{TYPED_JSON_TYPE}`")),
+        JsonValue::Object(obj) => typed_json_map(obj).map(RpcValue::from),
+    }
+}
+
+fn typed_json_list(list: &[serde_json::Value]) -> Result<shvproto::List, String> {
+    list.iter().map(from_typed_json).collect()
+}
+
+fn typed_json_map(obj: &serde_json::Map<String, serde_json::Value>) -> Result<shvproto::Map, String> {
+    obj.iter()
+        .map(|(key, val)| Ok((key.clone(), from_typed_json(val)?)))
+        .collect()
+}
+
+fn from_typed_json_envelope(envelope: &serde_json::Map<String, serde_json::Value>) -> Result<RpcValue, String> {
+    use serde_json::Value as JsonValue;
+    let type_name = envelope[TYPED_JSON_TYPE]
+        .as_str()
+        .ok_or_else(|| format!("`{TYPED_JSON_TYPE}` is not a string"))?;
+    if let Some(key) = envelope.keys().find(|key| ![TYPED_JSON_TYPE, TYPED_JSON_META, TYPED_JSON_VALUE].contains(&key.as_str())) {
+        return Err(format!("Unexpected key `{key}` in {type_name}"));
+    }
+    let json_value = envelope
+        .get(TYPED_JSON_VALUE)
+        .ok_or_else(|| format!("Missing `{TYPED_JSON_VALUE}` of {type_name}"))?;
+    let value = match (type_name, json_value) {
+        ("Null", JsonValue::Null) => Some(Value::Null),
+        ("Bool", JsonValue::Bool(b)) => Some(Value::Bool(*b)),
+        ("Int", JsonValue::Number(n)) => n.as_i64().map(Value::Int),
+        ("UInt", JsonValue::Number(n)) => n.as_u64().map(Value::UInt),
+        ("Double", JsonValue::Number(n)) => n.as_f64().map(Value::Double),
+        ("Double", JsonValue::String(s)) => match s.as_str() {
+            "NaN" => Some(Value::Double(f64::NAN)),
+            "Infinity" => Some(Value::Double(f64::INFINITY)),
+            "-Infinity" => Some(Value::Double(f64::NEG_INFINITY)),
+            _ => None,
+        },
+        ("Decimal", JsonValue::Object(decimal)) => {
+            let mantissa = decimal.get("mantissa").and_then(JsonValue::as_i64);
+            let exponent = decimal.get("exponent").and_then(JsonValue::as_i64).and_then(|exponent| i8::try_from(exponent).ok());
+            mantissa.zip(exponent).map(|(mantissa, exponent)| Value::Decimal(shvproto::Decimal::new(mantissa, exponent)))
+        }
+        ("DateTime", JsonValue::String(s)) => Some(Value::DateTime(shvproto::DateTime::from_iso_str(s)?)),
+        ("String", JsonValue::String(s)) => Some(Value::from(s.as_str())),
+        ("Blob", JsonValue::String(s)) => Some(Value::from(BASE64_STANDARD.decode(s).map_err(|e| format!("Invalid Blob: {e}"))?)),
+        ("List", JsonValue::Array(list)) => Some(Value::from(typed_json_list(list)?)),
+        ("Map", JsonValue::Object(obj)) => Some(Value::from(typed_json_map(obj)?)),
+        ("IMap", JsonValue::Object(obj)) => Some(Value::from(obj
+            .iter()
+            .map(|(key, val)| Ok((
+                key.parse::<i32>().map_err(|e| format!("Invalid IMap key `{key}`: {e}"))?,
+                from_typed_json(val)?,
+            )))
+            .collect::<Result<shvproto::IMap, String>>()?
+        )),
+        ("Null" | "Bool" | "Int" | "UInt" | "Double" | "Decimal" | "DateTime" | "String" | "Blob" | "List" | "Map" | "IMap", _) => None,
+        _ => return Err(format!("Unknown type `{type_name}`")),
+    }
+    .ok_or_else(|| format!("Invalid {type_name} value: {json_value}"))?;
+
+    let meta = match envelope.get(TYPED_JSON_META) {
+        None => None,
+        Some(JsonValue::Object(obj)) => {
+            let mut meta = MetaMap::new();
+            for (key, val) in obj {
+                let val = from_typed_json(val)?;
+                match key.parse::<i32>() {
+                    Ok(key) => meta.insert(key, val),
+                    Err(_) => meta.insert(key.as_str(), val),
+                };
+            }
+            Some(meta)
+        }
+        Some(meta) => return Err(format!("Invalid `{TYPED_JSON_META}` of {type_name}: {meta}")),
+    };
+    Ok(RpcValue::new(value, meta))
+}
+
+/// A request body with an `RpcValue` encoded according to its `Content-Type`
+struct RpcValueBody<T>(T);
 
 #[rocket::async_trait]
-impl<'r, T> rocket::data::FromData<'r> for RpcValueJson<T>
+impl<'r, T> rocket::data::FromData<'r> for RpcValueBody<T>
 where T: TryFrom<RpcValue>,
       T::Error: std::fmt::Display,
 {
@@ -372,21 +1653,21 @@ where T: TryFrom<RpcValue>,
 
     async fn from_data(req: &'r Request<'_>, data: rocket::Data<'r>) -> rocket::data::Outcome<'r, Self> {
         use rocket::data::Outcome;
-        if req.content_type() != Some(&rocket::http::ContentType::JSON) {
-            return_err!(req, Status::UnsupportedMediaType, "Expected Content-Type: application/json");
-        }
+        let Some(format) = req.content_type().and_then(|content_type| RpcValueFormat::from_media_type(content_type.media_type())) else {
+            return_err!(req, Status::UnsupportedMediaType, "Expected Content-Type: application/json, application/x-typed-json, application/x-cpon or application/x-chainpack");
+        };
 
         let limit = req.limits().get("json").unwrap_or(rocket::data::Limits::JSON);
 
-        let string = match data.open(limit).into_string().await {
-            Ok(string) if string.is_complete() => string.into_inner(),
+        let bytes = match data.open(limit).into_bytes().await {
+            Ok(bytes) if bytes.is_complete() => bytes.into_inner(),
             Ok(_) => return_err!(req, Status::PayloadTooLarge, "Payload too large"),
             Err(e) => return_err!(req, Status::InternalServerError, format!("{e}")),
         };
 
-        let value = match RpcValue::from_json(&string) {
+        let value = match format.decode(&bytes) {
             Ok(v) => v,
-            Err(err) => return_err!(req, Status::UnprocessableEntity, format!("Cannot parse JSON to RpcValue: {err}")),
+            Err(err) => return_err!(req, Status::UnprocessableEntity, format!("Cannot parse {format} to RpcValue: {err}")),
         };
 
         let res: T = match value.try_into() {
@@ -394,67 +1675,2004 @@ where T: TryFrom<RpcValue>,
             Err(err) => return_err!(req, Status::UnprocessableEntity, format!("Cannot convert RpcValue to the target type: {err}")),
         };
 
-        Outcome::Success(RpcValueJson(res))
+        Outcome::Success(RpcValueBody(res))
+    }
+}
+
+/// Responds with an `RpcValue` encoded in the format preferred by the `Accept` header
+struct RpcValueResponse(RpcValue);
+
+impl<'r> rocket::response::Responder<'r, 'static> for RpcValueResponse {
+    fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let format = RpcValueFormat::accepted(req);
+        match format.encode(&self.0) {
+            Ok(data) => (format.content_type(), data).respond_to(req),
+            Err(e) => err_response(Status::InternalServerError, format!("Cannot encode the response: {e}")).respond_to(req),
+        }
     }
 }
 
-#[post("/rpc", data = "<request>")]
-async fn api_rpc(session: Session, request: RpcValueJson<RpcRequest>) -> Result<RawJson<String>, ErrorResponse> {
-    let Session(_, SessionData { command_channel, session_channel, .. }) = session;
+/// Nginx's non-standard status for requests abandoned by the client
+const CLIENT_CLOSED_REQUEST: Status = Status::new(499);
+
+#[post("/rpc?<call_id>&<keepalive>", data = "<request>")]
+async fn api_rpc<'r>(
+    session: Session,
+    request: RpcValueBody<RpcRequest>,
+    call_id: Option<&str>,
+    keepalive: Option<bool>,
+    program_config: &'r State<Arc<ProgramConfig>>,
+) -> Result<rocket::Either<RpcValueResponse, RpcKeepaliveResponse<'r>>, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, session_channel, broker_connection, rpc_calls, dir_cache, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let RpcValueBody(request) = request;
+    let program_config: &ProgramConfig = program_config;
+    let mut cancellable_call = call_id
+        .map(|call_id| rpc_calls
+            .start(call_id)
+            .ok_or_else(|| err_response(Status::Conflict, format!("RPC call `{call_id}` is already in progress")))
+        )
+        .transpose()?;
+    let call = async move {
+        let call = exec_rpc_request(&command_channel, &dir_cache, request, program_config);
+        let Some(CancellableRpcCall { call_id, cancel_rx, .. }) = &mut cancellable_call else {
+            return call.await;
+        };
+        // Dropping the call future on cancellation aborts the call
+        tokio::select! {
+            result = call => result,
+            _ = cancel_rx => Err(err_response(CLIENT_CLOSED_REQUEST, format!("RPC call `{call_id}` has been cancelled"))),
+        }
+    };
+    if !keepalive.unwrap_or(false) {
+        return call.await.map(|result| rocket::Either::Left(RpcValueResponse(result)));
+    }
+
+    // Keep the session alive while the call is in progress
     session_channel
-        .unbounded_send(SessionEvent::Activity)
-        .unwrap_or_else(|e| error!("Cannot send SessionEvent::Activity: {e}"));
-    let RpcValueJson(request) = request;
-    let result: shvproto::RpcValue = RpcCall::new(&request.path, &request.method)
-        .param(request.param)
-        .exec(&command_channel)
-        .await
-        .map_err(err_response_rpc_call)?;
-    Ok(RawJson(result.to_json()))
+        .unbounded_send(SessionEvent::Subscription)
+        .unwrap_or_else(|e| error!("Cannot send SessionEvent::Subscription: {e}"));
+    let notifier = UnsubscribeNotifier(session_channel);
+    Ok(rocket::Either::Right(RpcKeepaliveResponse {
+        call: async move {
+            let _notifier = notifier;
+            call.await
+        }.boxed(),
+        interval: program_config.rpc_keepalive_interval,
+    }))
 }
 
-struct Session(String, SessionData);
+/// Streams the result of a call in the format of the items of
+/// `POST /api/rpc/batch`, preceded by spaces written periodically while the call is in progress. A closed
+/// connection fails a write, which drops the stream and aborts the call.
+struct RpcKeepaliveResponse<'a> {
+    call: BoxFuture<'a, Result<RpcValue, ErrorResponse>>,
+    interval: Duration,
+}
 
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for Session {
-    type Error = ErrorResponse;
+impl<'r> rocket::response::Responder<'r, 'r> for RpcKeepaliveResponse<'r> {
+    fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'r> {
+        let format = RpcValueFormat::accepted(req);
+        if format == RpcValueFormat::ChainPack {
+            return err_response(Status::NotAcceptable, "The keepalive spaces cannot be encoded in ChainPack").respond_to(req);
+        }
+        let mut response = self.into_stream(format).respond_to(req)?;
+        response.set_header(format.content_type());
+        Ok(response)
+    }
+}
 
+impl<'a> RpcKeepaliveResponse<'a> {
+    fn into_stream(self, format: RpcValueFormat) -> ByteStream![Vec<u8> + 'a] {
+        let Self { mut call, interval } = self;
+        ByteStream! {
+            let mut keepalive = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+            let result = loop {
+                tokio::select! {
+                    result = &mut call => break result,
+                    _ = keepalive.tick() => yield vec![b' '],
+                }
+            };
+            let item = result
+                .and_then(|value| format
+                    .encode(&shvproto::make_map!("result" => value).into())
+                    .map_err(|e| err_response(Status::InternalServerError, format!("Cannot encode the result: {e}")))
+                )
+                .or_else(|(_, Json(error))| format.encode(&rpc_batch_error_item(&error).into()));
+            match item {
+                Ok(item) => yield item,
+                Err(e) => error!("Cannot encode the keepalive call error: {e}"),
+            }
+        }
+    }
+}
 
-    async fn from_request(req: &'r Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
-        use rocket::request::Outcome;
-        let value = req
-            .headers()
-            .get_one("Authorization");
-        let Some(session_id) = value else {
-            return_err!(req, Status::BadRequest, "Missing Authorization header");
+/// A variant of `api_rpc` reporting the delay responses of the peer as
+/// `progress` events. The stream ends with a `result` or an `error` event.
+#[post("/rpc/stream?<typed_json>", data = "<request>")]
+async fn api_rpc_stream<'r>(
+    session: Session,
+    request: RpcValueBody<RpcRequest>,
+    typed_json: Option<bool>,
+    program_config: &'r State<Arc<ProgramConfig>>,
+) -> Result<EventStream![Event + 'r], ErrorResponse>
+{
+    let typed_json = typed_json.unwrap_or(false);
+    let Session(_, SessionData { command_channel, session_channel, broker_connection, dir_cache, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let RpcValueBody(request) = request;
+    let program_config: &ProgramConfig = program_config;
+
+    // Keep the session alive while the call is in progress
+    session_channel
+        .unbounded_send(SessionEvent::Subscription)
+        .unwrap_or_else(|e| error!("Cannot send SessionEvent::Subscription: {e}"));
+
+    Ok(EventStream! {
+        let _notifier = UnsubscribeNotifier(session_channel);
+        let (progress_tx, mut progress_rx) = channel::mpsc::unbounded();
+        // Dropping the call future when the client disconnects aborts the call
+        let mut call = std::pin::pin!(exec_rpc_request_with_progress(&command_channel, &dir_cache, request, program_config, program_config.rpc_timeout, Some(&progress_tx)));
+        let result = loop {
+            // Deliver all pending progress events before the result
+            let progress = tokio::select! {
+                biased;
+                Some(progress) = progress_rx.next() => progress,
+                result = &mut call => break result,
+            };
+            yield Event::data(progress.to_string()).event("progress");
+        };
+        // The call may report more progress in the same poll it finishes
+        while let Ok(progress) = progress_rx.try_recv() {
+            yield Event::data(progress.to_string()).event("progress");
+        }
+        match result {
+            Ok(value) => yield sse_value_event(&value, typed_json, Some("result")),
+            Err((_, Json(error))) => yield Event::json(&error).event("error"),
+        }
+    })
+}
+
+#[delete("/rpc/<call_id>")]
+async fn api_rpc_cancel(session: Session, call_id: &str) -> Result<(), ErrorResponse> {
+    let Session(_, SessionData { rpc_calls, .. }) = session;
+    if rpc_calls.cancel(call_id) {
+        Ok(())
+    } else {
+        Err(err_response(Status::NotFound, format!("RPC call `{call_id}` not found")))
+    }
+}
+
+const CLIENT_RPC_TIMEOUT_MARGIN: Duration = Duration::from_secs(1);
+
+/// Resolves the timeout of a call, which can be only lowered by the request
+fn rpc_call_timeout(request: &RpcRequest, default_timeout: Option<Duration>) -> Result<Option<Duration>, ErrorResponse> {
+    let request_timeout = request.timeout
+        .as_deref()
+        .map(duration_str::parse_std)
+        .transpose()
+        .map_err(|e| err_response(Status::UnprocessableEntity, format!("Invalid timeout: {e}")))?;
+    Ok(match (request_timeout, default_timeout) {
+        (Some(request_timeout), Some(default_timeout)) => Some(request_timeout.min(default_timeout)),
+        (request_timeout, default_timeout) => request_timeout.or(default_timeout),
+    })
+}
+
+/// Sends an SHV abort request for an unfinished call when dropped, that is
+/// when the call is cancelled, timed out by the gateway or abandoned.
+///
+/// shvclient does not expose the ID of an outgoing request, see
+/// `call_rpc_method` for how the ID is found.
+struct RpcCallAbort<'a> {
+    command_channel: &'a ClientCommandSender,
+    path: &'a str,
+    method: &'a str,
+    request_id: Option<RqId>,
+}
+
+impl Drop for RpcCallAbort<'_> {
+    fn drop(&mut self) {
+        let Some(request_id) = self.request_id else {
+            return;
         };
+        debug!("Aborting RPC call on path `{}`, method `{}`, request ID {request_id}", self.path, self.method);
+        let mut abort_request = RpcMessage::create_request_with_id(request_id, self.path, self.method);
+        abort_request.set_abort(AbortParam::Abort);
+        self.command_channel
+            .send_message(abort_request)
+            .unwrap_or_else(|e| warn!("Cannot send an abort request: {e}"));
+    }
+}
 
-        let Sessions(sessions) = req.rocket().state().expect("Sessions are present");
-        let Some(session_data) = sessions.read().await.get(session_id).cloned() else {
-            return_err!(req, Status::Unauthorized, "Invalid session token");
+async fn call_rpc_method(
+    command_channel: &ClientCommandSender,
+    path: &str,
+    method: &str,
+    param: Option<RpcValue>,
+    timeout: Option<Duration>,
+    progress: Option<&UnboundedSender<f64>>,
+) -> Result<RpcValue, CallRpcMethodError>
+{
+    let make_error = |kind| CallRpcMethodError::new(path, method, kind);
+    // `do_rpc_call` takes the request ID from the global counter. Drawing an ID
+    // right before and after it pins the ID of the request, unless another
+    // request has been created in between. The ID is then taken from the first
+    // delay response.
+    let id_before = RpcMessage::next_request_id();
+    let frames = command_channel.do_rpc_call(path, method, param, timeout.map(Into::into), None);
+    let id_after = RpcMessage::next_request_id();
+    let mut frames = frames.map_err(|_| make_error(CallRpcMethodErrorKind::ConnectionClosed))?;
+    let request_id = (id_after == id_before + 2).then_some(id_before + 1);
+    let mut abort = RpcCallAbort { command_channel, path, method, request_id };
+    let result = loop {
+        let Some(frame) = frames.next().await else {
+            break Err(make_error(CallRpcMethodErrorKind::ConnectionClosed));
         };
+        let rpcmsg = match frame.to_rpcmesage() {
+            Ok(rpcmsg) => rpcmsg,
+            Err(e) => break Err(make_error(CallRpcMethodErrorKind::InvalidMessage(e.to_string()))),
+        };
+        match rpcmsg.response() {
+            Ok(Response::Delay(delay)) => {
+                abort.request_id = rpcmsg.request_id();
+                if let Some(progress) = progress {
+                    progress
+                        .unbounded_send(delay)
+                        .unwrap_or_else(|e| debug!("Cannot report the progress of an RPC call: {e}"));
+                }
+            }
+            Ok(Response::Success(result)) => break Ok(result.clone()),
+            Err(err) => break Err(make_error(CallRpcMethodErrorKind::RpcError(err))),
+        }
+    };
+    // The call has finished, there is nothing to abort
+    abort.request_id = None;
+    result
+}
 
-        Outcome::Success(Session(session_id.into(), session_data))
+async fn exec_rpc_request(
+    command_channel: &ClientCommandSender,
+    dir_cache: &DirCache,
+    request: RpcRequest,
+    program_config: &ProgramConfig,
+) -> Result<RpcValue, ErrorResponse>
+{
+    exec_rpc_request_with_progress(command_channel, dir_cache, request, program_config, program_config.rpc_timeout, None).await
+}
+
+/// Executes an RPC request with the given default timeout and reports the
+/// delay values received from the peer to `progress`
+async fn exec_rpc_request_with_progress(
+    command_channel: &ClientCommandSender,
+    dir_cache: &DirCache,
+    mut request: RpcRequest,
+    program_config: &ProgramConfig,
+    default_timeout: Option<Duration>,
+    progress: Option<&UnboundedSender<f64>>,
+) -> Result<RpcValue, ErrorResponse>
+{
+    check_call_policy(program_config, &request.path, &request.method)?;
+    let timeout = rpc_call_timeout(&request, default_timeout)?;
+    if program_config.read_only {
+        check_read_only(command_channel, dir_cache, &request.path, &request.method, timeout, program_config).await?;
     }
+    if program_config.coerce_params && let Some(param) = request.param.take() {
+        request.param = Some(coerce_rpc_param(command_channel, dir_cache, &request.path, &request.method, param, timeout, program_config).await?);
+    }
+    let Some(timeout) = timeout else {
+        return call_rpc_method(command_channel, &request.path, &request.method, request.param, None, progress)
+            .await
+            .map_err(|e| err_response_rpc_call(e, program_config));
+    };
+    // The timeout of the client is longer, so that it does not win the race
+    // with the gateway timeout and the timeout is reported by the gateway.
+    let call = call_rpc_method(command_channel, &request.path, &request.method, request.param, Some(timeout + CLIENT_RPC_TIMEOUT_MARGIN), progress);
+    tokio::time::timeout(timeout, call)
+        .await
+        .map_err(|_| err_response_gateway_timeout(&request.path, &request.method, timeout))?
+        .map_err(|e| err_response_rpc_call(e, program_config))
 }
 
-#[catch(default)]
-fn catch_default(status: Status, req: &Request) -> ErrorResponse {
-    req.local_cache(|| err_response::<&str>(status, status.reason())).clone()
+/// Reports a call timed out by the gateway. Unlike a timeout reported by the
+/// SHV tree, it has no `shv_error` and its `rpc_error` is named `GatewayTimeout`.
+fn err_response_gateway_timeout(path: &str, method: &str, timeout: Duration) -> ErrorResponse {
+    let message = format!("RPC call on path `{path}`, method `{method}` timed out after {timeout:?}");
+    let (status, Json(mut body)) = err_response(Status::GatewayTimeout, &message);
+    body.rpc_error = Some(Box::new(RpcErrorInfo {
+        code: None,
+        name: "GatewayTimeout".into(),
+        message,
+        path: path.into(),
+        method: method.into(),
+    }));
+    (status, Json(body))
 }
 
-struct Random(pub(crate) Arc<Mutex<ChaCha20Rng>>);
+/// Permits only the methods listed by `dir` with an access level up to `Read`
+async fn check_read_only(
+    command_channel: &ClientCommandSender,
+    dir_cache: &DirCache,
+    path: &str,
+    method: &str,
+    timeout: Option<Duration>,
+    program_config: &ProgramConfig,
+) -> Result<(), ErrorResponse>
+{
+    let methods = dir_cache.methods(command_channel, path, timeout, program_config).await?;
+    match methods.iter().find(|method_info| method_info.name == method) {
+        None => Err(err_response(
+                Status::Forbidden,
+                format!("The gateway is read-only and method `{method}` is not listed by `dir` on path `{path}`")
+        )),
+        Some(method_info) if method_info.access_level > AccessLevel::Read => Err(err_response(
+                Status::Forbidden,
+                format!(
+                    "The gateway is read-only and method `{method}` on path `{path}` requires the `{}` access level",
+                    method_info.access_level.as_str()
+                )
+        )),
+        Some(_) => Ok(()),
+    }
+}
 
-#[derive(Debug, clap::Parser)]
-struct ProgramConfig {
-    #[arg(long)]
-    broker_url: Url,
-    #[arg(long, default_value = "10")]
-    max_user_sessions: i32,
-    #[arg(long, default_value = "10m", value_parser = |val: &str| duration_str::parse_std(val))]
-    session_timeout: Duration,
-    #[arg(long, default_value = "60s", value_parser = |val: &str| duration_str::parse_std(val))]
-    heartbeat_interval: Duration,
+/// Scalar param types, to which the params of calls can be coerced
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ParamType {
+    Null,
+    Bool,
+    Int,
+    UInt,
+    Double,
+    Decimal,
+    String,
+    DateTime,
+    Blob,
+}
+
+impl ParamType {
+    /// Accepts the type names of `RpcValue` and the SHV type info shortcuts.
+    /// Restrictions, e.g. `i(0,100)`, are ignored.
+    fn from_signature(signature: &str) -> Option<Self> {
+        let name = signature.split_once('(').map_or(signature, |(name, _)| name).trim();
+        match name {
+            "Null" | "n" => Some(Self::Null),
+            "Bool" | "b" => Some(Self::Bool),
+            "Int" | "i" => Some(Self::Int),
+            "UInt" | "u" => Some(Self::UInt),
+            "Double" | "f" => Some(Self::Double),
+            "Decimal" | "d" => Some(Self::Decimal),
+            "String" | "s" => Some(Self::String),
+            "DateTime" | "t" => Some(Self::DateTime),
+            "Blob" | "x" => Some(Self::Blob),
+            _ => None,
+        }
+    }
+
+    fn type_name(self) -> &'static str {
+        match self {
+            Self::Null => "Null",
+            Self::Bool => "Bool",
+            Self::Int => "Int",
+            Self::UInt => "UInt",
+            Self::Double => "Double",
+            Self::Decimal => "Decimal",
+            Self::String => "String",
+            Self::DateTime => "DateTime",
+            Self::Blob => "Blob",
+        }
+    }
+}
+
+/// Parses the param signature of a method from its `dir`. Signatures other
+/// than a scalar type or a union of scalar types, e.g. `Int|Null`, are not
+/// understood and such params are passed as they are.
+fn parse_param_types(signature: &str) -> Option<Vec<ParamType>> {
+    signature.split('|').map(ParamType::from_signature).collect()
+}
+
+/// Parses a decimal number like `-12.345` or `1.5e3` to a Decimal. Numbers that
+/// do not fit the Decimal without a loss of precision are rejected.
+fn parse_decimal(s: &str) -> Option<shvproto::Decimal> {
+    // The mantissa of Decimal has 56 bits
+    const MANTISSA_LIMIT: i64 = 1 << 55;
+    let (number, exponent) = match s.split_once(['e', 'E']) {
+        Some((number, exponent)) => (number, exponent.parse::<i32>().ok()?),
+        None => (s, 0),
+    };
+    let (int_part, frac_part) = number.split_once('.').unwrap_or((number, ""));
+    if !frac_part.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut digits = format!("{int_part}{frac_part}");
+    let mut exponent = exponent - i32::try_from(frac_part.len()).ok()?;
+    while digits.trim_start_matches(['-', '+']).len() > 1 && digits.ends_with('0') {
+        digits.pop();
+        exponent += 1;
+    }
+    let mantissa = digits.parse::<i64>().ok().filter(|mantissa| (-MANTISSA_LIMIT..MANTISSA_LIMIT).contains(mantissa))?;
+    Some(shvproto::Decimal::new(mantissa, i8::try_from(exponent).ok()?))
+}
+
+fn coerce_value(value: &RpcValue, target: ParamType) -> Result<RpcValue, String> {
+    // 2^63, the bounds of i64 and u64 are not exactly representable as f64
+    const I64_LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    let out_of_range = |_| "out of range".to_string();
+    let not_decimal = || "cannot be represented as Decimal".to_string();
+    let converted = match (&value.value, target) {
+        (Value::Int(n), ParamType::UInt) => Value::UInt(u64::try_from(*n).map_err(out_of_range)?),
+        (Value::UInt(n), ParamType::Int) => Value::Int(i64::try_from(*n).map_err(out_of_range)?),
+        (Value::Int(n), ParamType::Double) => Value::Double(*n as f64),
+        (Value::UInt(n), ParamType::Double) => Value::Double(*n as f64),
+        (Value::Int(n), ParamType::Decimal) => Value::Decimal(parse_decimal(&n.to_string()).ok_or_else(not_decimal)?),
+        (Value::UInt(n), ParamType::Decimal) => Value::Decimal(parse_decimal(&n.to_string()).ok_or_else(not_decimal)?),
+        (Value::Double(n), ParamType::Decimal) => Value::Decimal(parse_decimal(&n.to_string()).ok_or_else(not_decimal)?),
+        (Value::Double(n), ParamType::Int | ParamType::UInt) if n.fract() != 0.0 => return Err("not an integer".into()),
+        (Value::Double(n), ParamType::Int) if (-I64_LIMIT..I64_LIMIT).contains(n) => Value::Int(*n as i64),
+        (Value::Double(n), ParamType::UInt) if (0.0..2.0 * I64_LIMIT).contains(n) => Value::UInt(*n as u64),
+        (Value::Double(_), ParamType::Int | ParamType::UInt) => return Err("out of range".into()),
+        (Value::Decimal(d), ParamType::Double) => Value::Double(d.to_f64()),
+        (Value::String(s), ParamType::Decimal) => Value::Decimal(parse_decimal(s).ok_or_else(not_decimal)?),
+        (Value::String(s), ParamType::DateTime) => Value::DateTime(shvproto::DateTime::from_iso_str(s)?),
+        _ => return Err("incompatible types".into()),
+    };
+    Ok(RpcValue { meta: value.meta.clone(), value: converted })
+}
+
+/// Converts a param to one of the types, if it is not of any of them already
+fn coerce_param(param: RpcValue, types: &[ParamType]) -> Result<RpcValue, String> {
+    if types.iter().any(|param_type| param_type.type_name() == param.type_name()) {
+        return Ok(param);
+    }
+    let mut errors = vec![];
+    for param_type in types {
+        match coerce_value(&param, *param_type) {
+            Ok(value) => return Ok(value),
+            Err(e) => errors.push(format!("cannot convert {} `{}` to {}: {e}", param.type_name(), param.to_cpon(), param_type.type_name())),
+        }
+    }
+    Err(errors.join(", "))
+}
+
+/// Converts the param of a call to the param type of the method declared by
+/// its `dir`. The param is passed as it is if the method does not declare a
+/// scalar param type, a failure of `dir` fails the call.
+async fn coerce_rpc_param(
+    command_channel: &ClientCommandSender,
+    dir_cache: &DirCache,
+    path: &str,
+    method: &str,
+    param: RpcValue,
+    timeout: Option<Duration>,
+    program_config: &ProgramConfig,
+) -> Result<RpcValue, ErrorResponse>
+{
+    let methods = dir_cache.methods(command_channel, path, timeout, program_config).await?;
+    let Some(param_types) = methods
+        .iter()
+        .find(|method_info| method_info.name == method)
+        .and_then(|method_info| parse_param_types(&method_info.param)) else {
+        return Ok(param);
+    };
+    coerce_param(param, &param_types)
+        .map_err(|e| err_response(Status::UnprocessableEntity, format!("Invalid param of method `{method}` on path `{path}`: {e}")))
+}
+
+#[post("/rpc/batch?<stop_on_error>", data = "<requests>")]
+async fn api_rpc_batch(
+    session: Session,
+    requests: RpcValueBody<Vec<RpcRequest>>,
+    stop_on_error: Option<bool>,
+    program_config: &State<Arc<ProgramConfig>>,
+) -> Result<RpcValueResponse, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, broker_connection, dir_cache, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let RpcValueBody(requests) = requests;
+    check_batch_size(&requests, program_config)?;
+    let stop_on_error = stop_on_error.unwrap_or(false);
+
+    let mut results = std::iter::repeat_with(|| None).take(requests.len()).collect::<Vec<_>>();
+    let command_channel = &command_channel;
+    let dir_cache = &dir_cache;
+    let mut calls = rocket::futures::stream::iter(requests.into_iter().enumerate())
+        .map(|(idx, request)| async move {
+            (idx, exec_rpc_request(command_channel, dir_cache, request, program_config).await)
+        })
+        .buffer_unordered(program_config.batch_concurrency.max(1));
+    while let Some((idx, result)) = calls.next().await {
+        let failed = result.is_err();
+        results[idx] = Some(result);
+        if failed && stop_on_error {
+            // Dropping the rest of the calls abandons them
+            break;
+        }
+    }
+    drop(calls);
+    Ok(RpcValueResponse(rpc_batch_items(results)))
+}
+
+fn check_batch_size(requests: &[RpcRequest], program_config: &ProgramConfig) -> Result<(), ErrorResponse> {
+    if requests.len() > program_config.max_batch_size {
+        return Err(err_response(
+                Status::PayloadTooLarge,
+                format!("The batch has {} calls, at most {} are allowed", requests.len(), program_config.max_batch_size)
+        ));
+    }
+    Ok(())
+}
+
+/// Converts the results of batch calls to the items of the response, `None` marking a call not executed
+fn rpc_batch_items(results: Vec<Option<Result<RpcValue, ErrorResponse>>>) -> RpcValue {
+    results
+        .into_iter()
+        .map(|result| match result {
+            Some(Ok(value)) => shvproto::make_map!("result" => value),
+            Some(Err((_, Json(error)))) => rpc_batch_error_item(&error),
+            None => rpc_batch_error_item(&err_response(Status::FailedDependency, "Not executed due to a previous error").1.0),
+        })
+        .map(RpcValue::from)
+        .collect::<Vec<_>>()
+        .into()
+}
+
+#[post("/rpc/apply?<stop_on_error>&<dry_run>", data = "<requests>")]
+async fn api_rpc_apply(
+    session: Session,
+    requests: RpcValueBody<Vec<RpcRequest>>,
+    stop_on_error: Option<bool>,
+    dry_run: Option<bool>,
+    program_config: &State<Arc<ProgramConfig>>,
+) -> Result<RpcValueResponse, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, broker_connection, dir_cache, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let RpcValueBody(requests) = requests;
+    check_batch_size(&requests, program_config)?;
+    let stop_on_error = stop_on_error.unwrap_or(false);
+    let dry_run = dry_run.unwrap_or(false);
+
+    let mut results = std::iter::repeat_with(|| None).take(requests.len()).collect::<Vec<_>>();
+    for (result, request) in results.iter_mut().zip(requests) {
+        let call_result = if dry_run {
+            check_rpc_request(&command_channel, &dir_cache, &request, program_config)
+                .await
+                .map(|_| RpcValue::null())
+        } else {
+            exec_rpc_request(&command_channel, &dir_cache, request, program_config).await
+        };
+        let failed = call_result.is_err();
+        *result = Some(call_result);
+        if failed && stop_on_error {
+            break;
+        }
+    }
+    Ok(RpcValueResponse(rpc_batch_items(results)))
+}
+
+/// Checks that the method of the request exists and that the user has the access level required to call it
+async fn check_rpc_request(
+    command_channel: &ClientCommandSender,
+    dir_cache: &DirCache,
+    request: &RpcRequest,
+    program_config: &ProgramConfig,
+) -> Result<(), ErrorResponse>
+{
+    let RpcRequest { path, method, param, .. } = request;
+    check_call_policy(program_config, path, method)?;
+    let timeout = rpc_call_timeout(request, program_config.rpc_timeout)?;
+    let methods = dir_cache.methods(command_channel, path, timeout, program_config).await?;
+    let Some(method_info) = methods.iter().find(|method_info| &method_info.name == method) else {
+        return Err(err_response(Status::NotFound, format!("Method `{method}` does not exist on path `{path}`")));
+    };
+    if program_config.read_only {
+        check_read_only(command_channel, dir_cache, path, method, timeout, program_config).await?;
+    }
+    let access_level = call_rpc_method(
+        command_channel,
+        ".broker/currentClient",
+        "accessLevelForMethodCall",
+        Some(vec![RpcValue::from(path.as_str()), RpcValue::from(method.as_str())].into()),
+        timeout,
+        None,
+    )
+        .await
+        .map_err(|e| err_response_rpc_call(e, program_config))?;
+    if access_level.as_i32() < method_info.access_level as i32 {
+        return Err(err_response(
+                Status::Forbidden,
+                format!("Method `{method}` on path `{path}` requires the `{}` access level, which is not granted to the user", method_info.access_level.as_str())
+        ));
+    }
+    if program_config.coerce_params && let Some(param) = param {
+        coerce_rpc_param(command_channel, dir_cache, path, method, param.clone(), timeout, program_config).await?;
+    }
+    Ok(())
+}
+
+fn rpc_batch_error_item(error: &ErrorResponseBody) -> shvproto::rpcvalue::Map {
+    let error = serde_json::to_string(error).expect("ErrorResponseBody is serializable");
+    shvproto::make_map!("error" => RpcValue::from_json(error).expect("ErrorResponseBody is a valid RpcValue"))
+}
+
+enum JobState {
+    Running(tokio::task::AbortHandle),
+    /// The result and the time of finishing
+    Finished(Result<RpcValue, ErrorResponseBody>, std::time::Instant),
+}
+
+struct Job {
+    path: String,
+    method: String,
+    state: JobState,
+}
+
+impl Job {
+    fn status(&self) -> &'static str {
+        match &self.state {
+            JobState::Running(_) => "running",
+            JobState::Finished(Ok(_), _) => "succeeded",
+            JobState::Finished(Err(_), _) => "failed",
+        }
+    }
+
+    fn to_rpcvalue(&self, job_id: u64, with_result: bool) -> RpcValue {
+        let mut job = shvproto::make_map!(
+            "job_id" => job_id as i64,
+            "path" => self.path.as_str(),
+            "method" => self.method.as_str(),
+            "status" => self.status(),
+        );
+        if with_result && let JobState::Finished(result, _) = &self.state {
+            match result {
+                Ok(value) => { job.insert("result".into(), value.clone()); }
+                Err(error) => job.append(&mut rpc_batch_error_item(error)),
+            }
+        }
+        job.into()
+    }
+}
+
+/// RPC calls running in the background for the session
+#[derive(Clone)]
+struct SessionJobs {
+    jobs: Arc<std::sync::Mutex<HashMap<u64, Job>>>,
+    last_job_id: Arc<std::sync::atomic::AtomicU64>,
+    /// How long the finished jobs are kept
+    retention: Duration,
+}
+
+impl SessionJobs {
+    fn new(retention: Duration) -> Self {
+        Self {
+            jobs: Default::default(),
+            last_job_id: Default::default(),
+            retention,
+        }
+    }
+
+    /// Locks the jobs with the expired finished jobs removed
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Job>> {
+        let mut jobs = self.jobs.lock().expect("Jobs lock is not poisoned");
+        jobs.retain(|_, job| match job.state {
+            JobState::Running(_) => true,
+            JobState::Finished(_, finished_at) => finished_at.elapsed() < self.retention,
+        });
+        jobs
+    }
+
+    fn cancel_all(&self) {
+        for job in self.lock().values() {
+            if let JobState::Running(task) = &job.state {
+                task.abort();
+            }
+        }
+    }
+}
+
+/// Notifies the session task when a job finishes or is cancelled
+struct JobFinishedNotifier(UnboundedSender<SessionEvent>);
+
+impl Drop for JobFinishedNotifier {
+    fn drop(&mut self) {
+        self.0.unbounded_send(SessionEvent::JobFinished)
+            .unwrap_or_else(|e| if e.is_disconnected() {
+                // The jobs are cancelled when the session is removed
+                debug!("Session closed before the job finished");
+            } else {
+                error!("Cannot send SessionEvent::JobFinished: {e}");
+            });
+    }
+}
+
+#[post("/jobs", data = "<request>")]
+async fn api_jobs_start(
+    session: Session,
+    request: RpcValueBody<RpcRequest>,
+    program_config: &State<Arc<ProgramConfig>>,
+) -> Result<RpcValueResponse, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, session_channel, broker_connection, dir_cache, jobs, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let RpcValueBody(request) = request;
+    rpc_call_timeout(&request, None)?;
+
+    let mut jobs_guard = jobs.lock();
+    let running_jobs = jobs_guard.values().filter(|job| matches!(job.state, JobState::Running(_))).count();
+    if running_jobs >= program_config.max_session_jobs {
+        return Err(err_response(
+                Status::TooManyRequests,
+                format!("Maximum number of {} running jobs of the session reached", program_config.max_session_jobs)
+        ));
+    }
+    let job_id = jobs.last_job_id.fetch_add(1, Ordering::SeqCst) + 1;
+    let (path, method) = (request.path.clone(), request.method.clone());
+    session_channel
+        .unbounded_send(SessionEvent::JobStarted)
+        .unwrap_or_else(|e| error!("Cannot send SessionEvent::JobStarted: {e}"));
+    let task = {
+        let jobs = jobs.clone();
+        let notifier = JobFinishedNotifier(session_channel);
+        let program_config = Arc::clone(program_config.inner());
+        tokio::spawn(async move {
+            let _notifier = notifier;
+            // The default timeout is meant for the calls holding an HTTP request
+            let result = exec_rpc_request_with_progress(&command_channel, &dir_cache, request, &program_config, None, None)
+                .await
+                .map_err(|(_, Json(error))| error);
+            if let Some(job) = jobs.lock().get_mut(&job_id) {
+                job.state = JobState::Finished(result, std::time::Instant::now());
+            }
+        })
+    };
+    // The task cannot finish the job before it is inserted, the lock is held
+    let job = Job { path, method, state: JobState::Running(task.abort_handle()) };
+    let response = job.to_rpcvalue(job_id, false);
+    jobs_guard.insert(job_id, job);
+    Ok(RpcValueResponse(response))
+}
+
+#[get("/jobs")]
+async fn api_jobs(session: Session) -> RpcValueResponse {
+    let Session(_, SessionData { jobs, .. }) = session;
+    let mut list = jobs
+        .lock()
+        .iter()
+        .map(|(job_id, job)| (*job_id, job.to_rpcvalue(*job_id, false)))
+        .collect::<Vec<_>>();
+    list.sort_by_key(|(job_id, _)| *job_id);
+    RpcValueResponse(list.into_iter().map(|(_, job)| job).collect::<Vec<_>>().into())
+}
+
+#[get("/jobs/<job_id>")]
+async fn api_job(session: Session, job_id: u64) -> Result<RpcValueResponse, ErrorResponse> {
+    let Session(_, SessionData { jobs, .. }) = session;
+    jobs.lock()
+        .get(&job_id)
+        .map(|job| RpcValueResponse(job.to_rpcvalue(job_id, true)))
+        .ok_or_else(|| err_response(Status::NotFound, format!("Job {job_id} not found")))
+}
+
+/// Removes the job, a running job is cancelled
+#[delete("/jobs/<job_id>")]
+async fn api_job_remove(session: Session, job_id: u64) -> Result<(), ErrorResponse> {
+    let Session(_, SessionData { jobs, .. }) = session;
+    match jobs.lock().remove(&job_id) {
+        Some(Job { state: JobState::Running(task), .. }) => {
+            // Dropping the call aborts it on the peer
+            task.abort();
+            Ok(())
+        }
+        Some(_) => Ok(()),
+        None => Err(err_response(Status::NotFound, format!("Job {job_id} not found"))),
+    }
+}
+
+/// The node on the mount point of a session mounted as a device
+#[derive(Clone)]
+struct DeviceNode {
+    methods: Arc<[MetaMethod]>,
+    requests: DeviceRequests,
+    request_timeout: Duration,
+}
+
+impl DeviceNode {
+    async fn process_request(self, request: RpcMessage) -> shvclient::clientnode::RequestHandlerResult {
+        use shvclient::clientnode::Method;
+        // The node has no children
+        if !request.shv_path().unwrap_or_default().is_empty() {
+            return shvclient::clientnode::err_unresolved_request();
+        }
+        let methods = self.methods.to_vec();
+        match Method::from_request(&request) {
+            Method::Dir(dir) => dir.resolve(methods),
+            Method::Ls(ls) => ls.resolve(methods, async || Ok(Vec::new())),
+            Method::Other(method) => method.resolve(methods, async move || {
+                self.requests.forward(request, self.request_timeout).await
+            }),
+        }
+    }
+}
+
+/// An SHV request for a method of a session mounted as a device, as
+/// delivered to the HTTP client
+#[derive(Clone, shvproto::ToRpcValue)]
+struct DeviceRequest {
+    #[field_name = "request_id"]
+    request_id: u64,
+    method: String,
+    param: Option<RpcValue>,
+    #[field_name = "user_id"]
+    user_id: Option<String>,
+}
+
+type DeviceRequestResult = Result<RpcValue, shvrpc::rpcmessage::RpcError>;
+
+#[derive(Default)]
+struct DeviceRequestsState {
+    /// Requests waiting for a response of the HTTP client
+    pending: HashMap<u64, tokio::sync::oneshot::Sender<DeviceRequestResult>>,
+    /// Pending requests not delivered to the HTTP client yet
+    queue: std::collections::VecDeque<DeviceRequest>,
+}
+
+/// Queue of the requests for the methods of a session mounted as a device
+#[derive(Clone)]
+struct DeviceRequests {
+    /// The maximum number of the requests waiting for a response of the HTTP client
+    capacity: usize,
+    state: Arc<std::sync::Mutex<DeviceRequestsState>>,
+    queued: Arc<tokio::sync::Notify>,
+    last_request_id: Arc<std::sync::atomic::AtomicU64>,
+    /// Locked by the stream delivering the requests to the HTTP client
+    stream_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl DeviceRequests {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Default::default(),
+            queued: Default::default(),
+            last_request_id: Default::default(),
+            stream_lock: Default::default(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, DeviceRequestsState> {
+        self.state.lock().expect("Device requests lock is not poisoned")
+    }
+
+    /// Delivers the request to the HTTP client and waits for its response
+    async fn forward(&self, request: RpcMessage, timeout: Duration) -> DeviceRequestResult {
+        use shvrpc::rpcmessage::RpcError;
+        let request_id = self.last_request_id.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+        let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+        {
+            let mut state = self.lock();
+            if state.pending.len() >= self.capacity {
+                return Err(RpcError::new(
+                        RpcErrorCode::TryAgainLater,
+                        format!("{} requests are already waiting for the HTTP client", self.capacity)
+                ));
+            }
+            state.pending.insert(request_id, response_tx);
+            state.queue.push_back(DeviceRequest {
+                request_id,
+                method: request.method().unwrap_or_default().into(),
+                param: request.param().cloned(),
+                user_id: request.user_id().map(String::from),
+            });
+        }
+        self.queued.notify_one();
+        let response = tokio::time::timeout(timeout, response_rx).await;
+        {
+            // The request may have timed out before it was delivered
+            let mut state = self.lock();
+            state.pending.remove(&request_id);
+            state.queue.retain(|request| request.request_id != request_id);
+        }
+        match response {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(RpcError::new(RpcErrorCode::MethodCallCancelled, "The session has been closed")),
+            Err(_) => Err(RpcError::new(RpcErrorCode::MethodCallTimeout, format!("The HTTP client has not responded within {timeout:?}"))),
+        }
+    }
+
+    /// Waits for the next request to deliver to the HTTP client
+    ///
+    /// The request stays queued until it is marked as delivered, so that it is
+    /// delivered again if the stream is dropped before delivering it.
+    async fn next(&self) -> DeviceRequest {
+        loop {
+            if let Some(request) = self.lock().queue.front() {
+                return request.clone();
+            }
+            self.queued.notified().await;
+        }
+    }
+
+    /// Removes a request delivered to the HTTP client from the queue
+    fn delivered(&self, request_id: u64) {
+        self.lock().queue.retain(|request| request.request_id != request_id);
+    }
+}
+
+/// A session mounted as a device
+#[derive(Clone)]
+struct SessionDevice {
+    /// The mount point assigned by the broker
+    mount_point: String,
+    requests: DeviceRequests,
+}
+
+/// Checks the methods of a device mounted on `mount_point` against the policy
+fn check_device_policy(program_config: &ProgramConfig, mount_point: &str, methods: &[MetaMethod]) -> Result<(), ErrorResponse> {
+    methods
+        .iter()
+        .try_for_each(|method| check_call_policy(program_config, mount_point, &method.name))
+}
+
+/// Resolves and checks the mount point of a session mounted as a device, which
+/// the broker has assigned either from `mount` or from `device_id`
+async fn device_mount_point(
+    command_channel: &ClientCommandSender,
+    methods: &[MetaMethod],
+    program_config: &ProgramConfig,
+) -> Result<String, ErrorResponse>
+{
+    let info = call_rpc_method(command_channel, ".broker/currentClient", "info", None, program_config.rpc_timeout, None)
+        .await
+        .map_err(|e| err_response_rpc_call(e, program_config))?;
+    let mount_point = info.as_map().get("mountPoint").map(RpcValue::as_str).unwrap_or_default();
+    if mount_point.is_empty() {
+        return Err(err_response(Status::Forbidden, "The broker has not mounted the session"));
+    }
+    check_device_policy(program_config, mount_point, methods)?;
+    Ok(mount_point.into())
+}
+
+fn session_device(device: Option<SessionDevice>) -> Result<SessionDevice, ErrorResponse> {
+    device.ok_or_else(|| err_response(Status::NotFound, "The session is not mounted as a device"))
+}
+
+/// Streams the SHV requests for the methods of a session mounted as a device
+#[get("/device/requests?<typed_json>")]
+async fn api_device_requests(session: EventSourceSession, typed_json: Option<bool>) -> Result<EventStream![], ErrorResponse> {
+    let typed_json = typed_json.unwrap_or(false);
+    let EventSourceSession(Session(_, SessionData { session_channel, device, .. })) = session;
+    let SessionDevice { requests, .. } = session_device(device)?;
+    let stream_guard = requests.stream_lock
+        .clone()
+        .try_lock_owned()
+        .map_err(|_| err_response(Status::Conflict, "The device requests are already being streamed"))?;
+
+    session_channel
+        .unbounded_send(SessionEvent::Subscription)
+        .unwrap_or_else(|e| error!("Cannot send SessionEvent::Subscription: {e}"));
+
+    Ok(EventStream! {
+        // Notify the session task when the EventStream finishes
+        let _notifier = UnsubscribeNotifier(session_channel);
+        let _stream_guard = stream_guard;
+        loop {
+            let request = requests.next().await;
+            let request_id = request.request_id;
+            yield sse_value_event(&RpcValue::from(request), typed_json, Some("request"));
+            // The stream is resumed only after the event has been passed on
+            requests.delivered(request_id);
+        }
+    })
+}
+
+#[derive(shvproto::FromRpcValue)]
+struct DeviceResponse {
+    result: Option<RpcValue>,
+    error: Option<DeviceResponseError>,
+}
+
+#[derive(shvproto::FromRpcValue)]
+struct DeviceResponseError {
+    code: Option<i64>,
+    message: String,
+}
+
+/// Responds to an SHV request delivered by `api_device_requests`
+#[post("/device/requests/<request_id>", data = "<response>")]
+async fn api_device_respond(
+    session: Session,
+    request_id: u64,
+    response: RpcValueBody<DeviceResponse>,
+) -> Result<Status, ErrorResponse>
+{
+    let Session(_, SessionData { device, .. }) = session;
+    let device = session_device(device)?;
+    let RpcValueBody(DeviceResponse { result, error }) = response;
+    let result = match error {
+        None => Ok(result.unwrap_or_else(RpcValue::null)),
+        Some(DeviceResponseError { code, message }) => {
+            let code = code
+                .map(u32::try_from)
+                .transpose()
+                .map_err(|e| err_response(Status::UnprocessableEntity, format!("Invalid error code: {e}")))?
+                .map_or(RpcErrorCodeKind::RpcError(RpcErrorCode::MethodCallException), RpcErrorCodeKind::from);
+            Err(shvrpc::rpcmessage::RpcError::new(code, message))
+        }
+    };
+    let not_pending = || err_response(Status::NotFound, format!("Device request {request_id} is not pending"));
+    let response_tx = device.requests.lock().pending.remove(&request_id).ok_or_else(not_pending)?;
+    response_tx.send(result).map_err(|_| not_pending())?;
+    Ok(Status::NoContent)
+}
+
+#[derive(shvproto::FromRpcValue)]
+struct SignalRequest {
+    path: Option<String>,
+    signal: String,
+    source: Option<String>,
+    param: Option<RpcValue>,
+}
+
+/// Checks a path relative to the mount point of a session, which must not
+/// leave the subtree of the session
+fn check_device_path(path: &str) -> Result<(), ErrorResponse> {
+    if path.is_empty() {
+        return Ok(());
+    }
+    for segment in path.split('/') {
+        match segment {
+            ".." => return Err(err_response(Status::Forbidden, format!("Path `{path}` is outside of the subtree of the session"))),
+            "" | "." => return Err(err_response(Status::UnprocessableEntity, format!("Invalid path `{path}`"))),
+            _ => { }
+        }
+    }
+    Ok(())
+}
+
+/// Emits a signal on a path relative to the mount point of a session mounted
+/// as a device
+#[post("/device/signals", data = "<request>")]
+async fn api_device_signal(
+    session: Session,
+    request: RpcValueBody<SignalRequest>,
+    program_config: &State<Arc<ProgramConfig>>,
+) -> Result<Status, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, broker_connection, device, .. }) = session;
+    let SessionDevice { mount_point, .. } = session_device(device)?;
+    if program_config.read_only {
+        return Err(err_response(Status::Forbidden, "The gateway is read-only and cannot emit signals"));
+    }
+    check_broker_connection(&broker_connection)?;
+    let RpcValueBody(SignalRequest { path, signal, source, param }) = request;
+    let path = path.unwrap_or_default();
+    check_device_path(&path)?;
+    if signal.is_empty() {
+        return Err(err_response(Status::UnprocessableEntity, "Missing signal name"));
+    }
+    let source = source.as_deref().unwrap_or("get");
+    let full_path = if path.is_empty() { mount_point } else { format!("{mount_point}/{path}") };
+    let shv_ri = ShvRI::from_path_method_signal(&full_path, source, Some(&signal))
+        .map_err(|e| err_response(Status::UnprocessableEntity, e))?;
+    check_policy(program_config.policy.as_ref(), &shv_ri)?;
+    // The broker prefixes the path with the mount point of the session
+    let mut message = RpcMessage::new_signal_with_source(&path, &signal, source);
+    message.set_param_opt(param);
+    command_channel
+        .send_message(message)
+        .map_err(|e| err_response(Status::ServiceUnavailable, format!("Cannot send the signal: {e}")))?;
+    Ok(Status::NoContent)
+}
+
+/// Joins the percent-decoded segments of a `<path..>` route parameter to an SHV path
+fn shv_path(segments: Segments<'_, UriPath>) -> String {
+    segments.collect::<Vec<_>>().join("/")
+}
+
+#[get("/value/<path..>")]
+async fn api_value_get(
+    session: Session,
+    path: Segments<'_, UriPath>,
+    program_config: &State<Arc<ProgramConfig>>,
+) -> Result<RpcValueResponse, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, broker_connection, dir_cache, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let request = RpcRequest { path: shv_path(path), method: "get".into(), param: None, timeout: None };
+    exec_rpc_request(&command_channel, &dir_cache, request, program_config)
+        .await
+        .map(RpcValueResponse)
+}
+
+#[put("/value/<path..>", data = "<value>")]
+async fn api_value_put(
+    session: Session,
+    path: Segments<'_, UriPath>,
+    value: RpcValueBody<RpcValue>,
+    program_config: &State<Arc<ProgramConfig>>,
+) -> Result<Status, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, broker_connection, dir_cache, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let RpcValueBody(value) = value;
+    let request = RpcRequest { path: shv_path(path), method: "set".into(), param: Some(value), timeout: None };
+    exec_rpc_request(&command_channel, &dir_cache, request, program_config).await?;
+    Ok(Status::NoContent)
+}
+
+/// Method of an SHV node, as returned by `GET /api/dir`
+#[derive(Debug, Serialize)]
+struct MethodDescriptor {
+    name: String,
+    #[serde(serialize_with = "serialize_flags")]
+    flags: Flags,
+    access: &'static str,
+    param: String,
+    result: String,
+    signals: BTreeMap<String, Option<String>>,
+}
+
+impl From<&MethodInfo> for MethodDescriptor {
+    fn from(method: &MethodInfo) -> Self {
+        Self {
+            name: method.name.clone(),
+            flags: method.flags,
+            access: method.access_level.as_str(),
+            param: method.param.clone(),
+            result: method.result.clone(),
+            signals: method.signals.clone(),
+        }
+    }
+}
+
+fn serialize_flags<S: serde::Serializer>(flags: &Flags, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(flags.iter_names().map(|(name, _)| name))
+}
+
+/// Node of the SHV tree with the levels of its subtree that were fetched
+#[derive(Debug, Serialize)]
+struct NodeDescriptor {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    methods: Option<Vec<MethodDescriptor>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nodes: Option<Vec<NodeDescriptor>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<NodeError>,
+}
+
+/// The failed `ls` or `dir` call of a node, recorded by a tree walk
+#[derive(Debug, Serialize)]
+struct NodeError {
+    method: &'static str,
+    #[serde(flatten)]
+    error: ErrorResponseBody,
+}
+
+fn join_shv_path(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.into()
+    } else {
+        format!("{path}/{name}")
+    }
+}
+
+/// Walk of an SHV subtree with bounded number of concurrent calls and of visited nodes
+struct TreeWalk<'a> {
+    command_channel: &'a ClientCommandSender,
+    program_config: &'a ProgramConfig,
+    with_methods: bool,
+    max_nodes: usize,
+    /// Whether the failures of the nodes below the walked path are recorded
+    /// on the nodes instead of failing the whole walk
+    record_errors: bool,
+    node_count: AtomicUsize,
+    calls: tokio::sync::Semaphore,
+}
+
+impl<'a> TreeWalk<'a> {
+    fn new(command_channel: &'a ClientCommandSender, program_config: &'a ProgramConfig, with_methods: bool, max_nodes: usize) -> Self {
+        Self {
+            command_channel,
+            program_config,
+            with_methods,
+            max_nodes,
+            record_errors: false,
+            node_count: AtomicUsize::new(0),
+            calls: tokio::sync::Semaphore::new(program_config.tree_concurrency.max(1)),
+        }
+    }
+
+    fn record_errors(self) -> Self {
+        Self { record_errors: true, ..self }
+    }
+
+    /// Fetches the node on `path` with `levels` levels of its subtree
+    async fn browse(&self, path: &str, levels: usize) -> Result<NodeDescriptor, ErrorResponse> {
+        self.count_nodes(path, 1)?;
+        self.browse_node(path, None, levels).await
+    }
+
+    fn count_nodes(&self, path: &str, count: usize) -> Result<(), ErrorResponse> {
+        if self.node_count.fetch_add(count, Ordering::Relaxed) + count > self.max_nodes {
+            return Err(err_response(
+                    Status::UnprocessableEntity,
+                    format!("Node limit of {} exceeded while browsing `{path}`", self.max_nodes)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Calls `dir` and `ls` on `path`, the error is tagged with the failed method
+    async fn discover(&self, path: &str, levels: usize) -> Result<(Option<Vec<MethodDescriptor>>, Option<Vec<String>>), (&'static str, ErrorResponse)> {
+        let timeout = self.program_config.rpc_timeout.map(Into::into);
+        let _permit = self.calls.acquire().await.expect("Tree walk semaphore is not closed");
+        let methods = if self.with_methods {
+            check_call_policy(self.program_config, path, "dir").map_err(|e| ("dir", e))?;
+            let methods = self.command_channel
+                .call_dir_brief(path, timeout, None)
+                .await
+                .map_err(|e| ("dir", err_response_rpc_call(e, self.program_config)))?;
+            Some(methods.iter().map(MethodDescriptor::from).collect::<Vec<_>>())
+        } else {
+            None
+        };
+        let children = if levels > 0 {
+            check_call_policy(self.program_config, path, "ls").map_err(|e| ("ls", e))?;
+            let children = self.command_channel
+                .call_ls_list(path, timeout, None)
+                .await
+                .map_err(|e| ("ls", err_response_rpc_call(e, self.program_config)))?;
+            Some(children)
+        } else {
+            None
+        };
+        Ok((methods, children))
+    }
+
+    fn browse_node<'b>(&'b self, path: &'b str, name: Option<String>, levels: usize) -> BoxFuture<'b, Result<NodeDescriptor, ErrorResponse>> {
+        async move {
+            let (methods, children) = match self.discover(path, levels).await {
+                Ok(discovered) => discovered,
+                // The walked path itself has no parent to report the error
+                Err((method, (_, Json(error)))) if self.record_errors && name.is_some() => {
+                    return Ok(NodeDescriptor { name, methods: None, nodes: None, error: Some(NodeError { method, error }) });
+                }
+                Err((_, error)) => return Err(error),
+            };
+            let nodes = match children {
+                Some(children) => {
+                    self.count_nodes(path, children.len())?;
+                    let nodes = children
+                        .into_iter()
+                        .map(|child| async move {
+                            let child_path = join_shv_path(path, &child);
+                            self.browse_node(&child_path, Some(child), levels - 1).await
+                        });
+                    Some(rocket::futures::future::try_join_all(nodes).await?)
+                }
+                None => None,
+            };
+            Ok(NodeDescriptor { name, methods, nodes, error: None })
+        }.boxed()
+    }
+}
+
+fn browse_depth(depth: Option<usize>, default: usize, program_config: &ProgramConfig) -> Result<usize, ErrorResponse> {
+    match depth.unwrap_or(default) {
+        0 => Err(err_response(Status::BadRequest, "Depth must be at least 1")),
+        depth if depth > program_config.tree_max_depth => Err(err_response(
+                Status::BadRequest,
+                format!("Depth must not exceed {}", program_config.tree_max_depth)
+        )),
+        depth => Ok(depth),
+    }
+}
+
+fn browse_max_nodes(max_nodes: Option<usize>, program_config: &ProgramConfig) -> Result<usize, ErrorResponse> {
+    match max_nodes {
+        Some(max_nodes) if max_nodes > program_config.tree_max_nodes => Err(err_response(
+                Status::BadRequest,
+                format!("Node limit must not exceed {}", program_config.tree_max_nodes)
+        )),
+        Some(max_nodes) => Ok(max_nodes),
+        None => Ok(program_config.tree_max_nodes),
+    }
+}
+
+#[get("/ls/<path..>?<depth>")]
+async fn api_ls(
+    session: Session,
+    path: Segments<'_, UriPath>,
+    depth: Option<usize>,
+    program_config: &State<Arc<ProgramConfig>>,
+) -> Result<Json<Vec<NodeDescriptor>>, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, broker_connection, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let depth = browse_depth(depth, 1, program_config)?;
+    TreeWalk::new(&command_channel, program_config, false, program_config.tree_max_nodes)
+        .browse(&shv_path(path), depth)
+        .await
+        .map(|node| Json(node.nodes.unwrap_or_default()))
+}
+
+#[get("/dir/<path..>?<depth>")]
+async fn api_dir(
+    session: Session,
+    path: Segments<'_, UriPath>,
+    depth: Option<usize>,
+    program_config: &State<Arc<ProgramConfig>>,
+) -> Result<Json<NodeDescriptor>, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, broker_connection, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let depth = browse_depth(depth, 1, program_config)?;
+    TreeWalk::new(&command_channel, program_config, true, program_config.tree_max_nodes)
+        .browse(&shv_path(path), depth - 1)
+        .await
+        .map(Json)
+}
+
+/// Snapshot of an SHV subtree returned by `GET /api/tree`
+#[derive(Debug, Serialize)]
+struct TreeSnapshot {
+    path: String,
+    node_count: usize,
+    #[serde(flatten)]
+    root: NodeDescriptor,
+}
+
+#[get("/tree/<path..>?<depth>&<max_nodes>")]
+async fn api_tree(
+    session: Session,
+    path: Segments<'_, UriPath>,
+    depth: Option<usize>,
+    max_nodes: Option<usize>,
+    program_config: &State<Arc<ProgramConfig>>,
+) -> Result<Json<TreeSnapshot>, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, broker_connection, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let depth = browse_depth(depth, program_config.tree_max_depth, program_config)?;
+    let max_nodes = browse_max_nodes(max_nodes, program_config)?;
+    let path = shv_path(path);
+    let walk = TreeWalk::new(&command_channel, program_config, true, max_nodes).record_errors();
+    let root = walk.browse(&path, depth - 1).await?;
+    Ok(Json(TreeSnapshot { path, node_count: walk.node_count.into_inner(), root }))
+}
+
+/// Collects the `(path, method)` of the getters of `node` and of its subtree
+fn collect_getters(node: &NodeDescriptor, path: &str, getters: &mut Vec<(String, String)>) {
+    getters.extend(node.methods
+        .iter()
+        .flatten()
+        .filter(|method| method.flags.contains(Flags::IsGetter))
+        .map(|method| (path.to_string(), method.name.clone()))
+    );
+    for child in node.nodes.iter().flatten() {
+        let child_name = child.name.as_deref().unwrap_or_default();
+        collect_getters(child, &join_shv_path(path, child_name), getters);
+    }
+}
+
+/// Collects the failed `ls` and `dir` calls of the subtree of `node` by the
+/// paths of the nodes
+fn collect_node_errors(node: &NodeDescriptor, path: &str, errors: &mut shvproto::rpcvalue::Map) {
+    if let Some(error) = &node.error {
+        let error = serde_json::to_string(error).expect("NodeError is serializable");
+        errors.insert(path.into(), RpcValue::from_json(error).expect("NodeError is a valid RpcValue"));
+    }
+    for child in node.nodes.iter().flatten() {
+        let child_name = child.name.as_deref().unwrap_or_default();
+        collect_node_errors(child, &join_shv_path(path, child_name), errors);
+    }
+}
+
+#[get("/values/<path..>?<depth>&<max_nodes>")]
+async fn api_values(
+    session: Session,
+    path: Segments<'_, UriPath>,
+    depth: Option<usize>,
+    max_nodes: Option<usize>,
+    program_config: &State<Arc<ProgramConfig>>,
+) -> Result<RpcValueResponse, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, broker_connection, dir_cache, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let depth = browse_depth(depth, program_config.tree_max_depth, program_config)?;
+    let max_nodes = browse_max_nodes(max_nodes, program_config)?;
+    let path = shv_path(path);
+    let root = TreeWalk::new(&command_channel, program_config, true, max_nodes)
+        .record_errors()
+        .browse(&path, depth - 1)
+        .await?;
+    let mut getters = vec![];
+    collect_getters(&root, &path, &mut getters);
+    let mut node_errors = shvproto::rpcvalue::Map::new();
+    collect_node_errors(&root, &path, &mut node_errors);
+
+    let values = rocket::futures::stream::iter(getters)
+        .map(|(path, method)| {
+            let command_channel = &command_channel;
+            let dir_cache = &dir_cache;
+            async move {
+                let shv_ri = format!("{path}:{method}");
+                let request = RpcRequest { path, method, param: None, timeout: None };
+                let item = match exec_rpc_request(command_channel, dir_cache, request, program_config).await {
+                    Ok(value) => shvproto::make_map!("result" => value),
+                    Err((_, Json(error))) => rpc_batch_error_item(&error),
+                };
+                (shv_ri, RpcValue::from(item))
+            }
+        })
+        .buffer_unordered(program_config.tree_concurrency.max(1))
+        .collect::<shvproto::rpcvalue::Map>()
+        .await;
+    Ok(RpcValueResponse(shvproto::make_map!("values" => values, "errors" => node_errors).into()))
+}
+
+/// The value of the `Range` header of a download request
+struct RangeHeader<'r>(&'r str);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RangeHeader<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
+        use rocket::request::Outcome;
+        match req.headers().get_one("Range") {
+            Some(range) => Outcome::Success(RangeHeader(range)),
+            None => Outcome::Forward(Status::Ok),
+        }
+    }
+}
+
+/// Parses a `Range` header with a single byte range. The unsupported and
+/// malformed ranges are ignored and the whole content is sent instead.
+fn parse_byte_range(header: &str, file_size: u64) -> Result<Option<std::ops::Range<u64>>, ErrorResponse> {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    let Some((start, end)) = spec.split_once('-').filter(|_| !spec.contains(',')) else {
+        return Ok(None);
+    };
+    let range = match (start.trim().parse::<u64>().ok(), end.trim().parse::<u64>().ok()) {
+        (None, Some(suffix_length)) if start.trim().is_empty() => file_size.saturating_sub(suffix_length)..file_size,
+        (Some(start), None) if end.trim().is_empty() => start..file_size,
+        (Some(start), Some(end)) if start <= end => start..end.saturating_add(1).min(file_size),
+        _ => return Ok(None),
+    };
+    if range.is_empty() {
+        return Err(err_response(
+                Status::RangeNotSatisfiable,
+                format!("Range `{header}` is not satisfiable for the file size of {file_size} bytes")
+        ));
+    }
+    Ok(Some(range))
+}
+
+/// Content of an SHV file node, or its byte range, streamed by `body`
+struct FileDownload<R> {
+    body: R,
+    file_size: u64,
+    range: Option<std::ops::Range<u64>>,
+}
+
+impl<'r, 'o: 'r, R: rocket::response::Responder<'r, 'o>> rocket::response::Responder<'r, 'o> for FileDownload<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'o> {
+        let mut response = self.body.respond_to(req)?;
+        response.set_raw_header("Accept-Ranges", "bytes");
+        let content_length = match self.range {
+            Some(range) => {
+                response.set_status(Status::PartialContent);
+                response.set_raw_header("Content-Range", format!("bytes {}-{}/{}", range.start, range.end - 1, self.file_size));
+                range.end - range.start
+            }
+            None => self.file_size,
+        };
+        // The body is streamed, so the length is not known to Rocket
+        response.set_raw_header("Content-Length", content_length.to_string());
+        Ok(response)
+    }
+}
+
+/// Key of the file size in the result of the `stat` method of SHV file nodes
+const FILE_STAT_SIZE: i32 = 1;
+
+#[get("/file/<path..>")]
+async fn api_file_get<'r>(
+    session: Session,
+    path: Segments<'_, UriPath>,
+    range: Option<RangeHeader<'_>>,
+    program_config: &'r State<Arc<ProgramConfig>>,
+) -> Result<FileDownload<ByteStream![Vec<u8> + 'r]>, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, session_channel, broker_connection, dir_cache, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let path = shv_path(path);
+    let request = RpcRequest { path: path.clone(), method: "stat".into(), param: None, timeout: None };
+    let stat = exec_rpc_request(&command_channel, &dir_cache, request, program_config).await?;
+    let Some(file_size) = stat.as_imap().get(&FILE_STAT_SIZE).and_then(|size| u64::try_from(size.as_i64()).ok()) else {
+        let e = CallRpcMethodError::new(&path, "stat", CallRpcMethodErrorKind::ResultTypeMismatch(format!("No file size in `{}`", stat.to_cpon())));
+        return Err(err_response_rpc_call(e, program_config));
+    };
+    let range = match range {
+        Some(RangeHeader(range)) => parse_byte_range(range, file_size)?,
+        None => None,
+    };
+    let chunk_size = program_config.file_chunk_size.max(1);
+    let mut offset = range.as_ref().map_or(0, |range| range.start);
+    let end = range.as_ref().map_or(file_size, |range| range.end);
+    let body = ByteStream! {
+        // An incomplete body is detected by the client from `Content-Length`
+        while offset < end {
+            // Keep the session alive during a long download
+            touch_session(&session_channel);
+            let size = (end - offset).min(chunk_size);
+            let request = RpcRequest {
+                path: path.clone(),
+                method: "read".into(),
+                param: Some(vec![RpcValue::from(offset as i64), RpcValue::from(size as i64)].into()),
+                timeout: None,
+            };
+            match exec_rpc_request(&command_channel, &dir_cache, request, program_config).await {
+                Ok(RpcValue { value: Value::Blob(mut data), .. }) if !data.is_empty() => {
+                    data.truncate(size as usize);
+                    offset += data.len() as u64;
+                    yield *data;
+                }
+                Ok(data) => {
+                    warn!("Unexpected result of `read` of file `{path}` at offset {offset}: {}", data.to_cpon());
+                    break;
+                }
+                Err((_, Json(error))) => {
+                    warn!("Cannot read file `{path}` at offset {offset}: {}", error.detail);
+                    break;
+                }
+            }
+        }
+    };
+    Ok(FileDownload { body, file_size, range })
+}
+
+/// Key of the maximal size of a `write` call in the result of the `stat` method of SHV file nodes
+const FILE_STAT_MAX_WRITE: i32 = 5;
+
+/// Checksums of an uploaded file verified by the file node
+#[derive(Clone, Copy, Debug, PartialEq, rocket::FromFormField)]
+enum FileChecksum {
+    Crc,
+    Sha1,
+}
+
+static CRC_32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// Computes the checksum of an uploaded file selected by the `verify` parameter
+enum FileHasher {
+    Crc(crc::Digest<'static, u32>),
+    Sha1(sha1::Sha1),
+}
+
+impl FileHasher {
+    fn new(checksum: FileChecksum) -> Self {
+        match checksum {
+            FileChecksum::Crc => FileHasher::Crc(CRC_32.digest()),
+            FileChecksum::Sha1 => FileHasher::Sha1(<sha1::Sha1 as sha1::Digest>::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            FileHasher::Crc(digest) => digest.update(data),
+            FileHasher::Sha1(hasher) => sha1::Digest::update(hasher, data),
+        }
+    }
+}
+
+/// Content of an uploaded file, either the whole body or the first part of a multipart body
+enum FileUpload<'r> {
+    Raw(rocket::data::DataStream<'r>),
+    Multipart(multer::Field<'r>),
+}
+
+impl FileUpload<'_> {
+    /// Reads the next bytes of the content, `None` at its end
+    async fn next_bytes(&mut self) -> Result<Option<Vec<u8>>, String> {
+        match self {
+            FileUpload::Raw(stream) => {
+                let mut buf = vec![0; 64 * 1024];
+                let len = tokio::io::AsyncReadExt::read(stream, &mut buf).await.map_err(|e| e.to_string())?;
+                buf.truncate(len);
+                Ok(Some(buf).filter(|buf| !buf.is_empty()))
+            }
+            FileUpload::Multipart(field) => field
+                .chunk()
+                .await
+                .map(|chunk| chunk.map(|chunk| chunk.to_vec()))
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(test, derive(Deserialize))]
+struct FileUploadReport {
+    written: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crc: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha1: Option<String>,
+}
+
+#[put("/file/<path..>?<offset>&<verify>", data = "<data>")]
+async fn api_file_put(
+    session: Session,
+    path: Segments<'_, UriPath>,
+    offset: Option<u64>,
+    verify: Option<FileChecksum>,
+    content_type: Option<&ContentType>,
+    data: rocket::Data<'_>,
+    program_config: &State<Arc<ProgramConfig>>,
+) -> Result<Json<FileUploadReport>, ErrorResponse>
+{
+    let Session(_, SessionData { command_channel, session_channel, broker_connection, dir_cache, .. }) = session;
+    check_broker_connection(&broker_connection)?;
+    let path = shv_path(path);
+    let start_offset = offset.unwrap_or(0);
+
+    let request = RpcRequest { path: path.clone(), method: "stat".into(), param: None, timeout: None };
+    let stat = exec_rpc_request(&command_channel, &dir_cache, request, program_config).await?;
+    let max_write = stat
+        .as_imap()
+        .get(&FILE_STAT_MAX_WRITE)
+        .and_then(|max_write| u64::try_from(max_write.as_i64()).ok())
+        .filter(|max_write| *max_write > 0);
+    let chunk_size = program_config.file_chunk_size.min(max_write.unwrap_or(u64::MAX)).max(1) as usize;
+
+    // Read one byte over the limit to tell a complete upload from a truncated one
+    let stream = data.open(program_config.max_file_size + 1);
+    let mut upload = match content_type {
+        Some(content_type) if content_type.is_form_data() => {
+            let boundary = multer::parse_boundary(content_type.to_string())
+                .map_err(|e| err_response(Status::BadRequest, e.to_string()))?;
+            let field = multer::Multipart::with_reader(stream, boundary)
+                .next_field()
+                .await
+                .map_err(|e| err_response(Status::BadRequest, e.to_string()))?
+                .ok_or_else(|| err_response(Status::BadRequest, "The multipart body has no part"))?;
+            FileUpload::Multipart(field)
+        }
+        _ => FileUpload::Raw(stream),
+    };
+
+    let mut hasher = verify.map(FileHasher::new);
+    let mut written = 0;
+    let mut buf = vec![];
+    let mut content_end = false;
+    while !content_end || !buf.is_empty() {
+        while !content_end && buf.len() < chunk_size {
+            match upload.next_bytes().await {
+                Ok(Some(bytes)) => buf.extend_from_slice(&bytes),
+                Ok(None) => content_end = true,
+                Err(e) => return Err(err_response(
+                        Status::BadRequest,
+                        format!("Cannot read the uploaded content after writing {written} bytes: {e}")
+                )),
+            }
+        }
+        let chunk = buf.drain(..buf.len().min(chunk_size)).collect::<Vec<_>>();
+        if chunk.is_empty() {
+            break;
+        }
+        if written + chunk.len() as u64 > program_config.max_file_size.as_u64() {
+            return Err(err_response(
+                    Status::PayloadTooLarge,
+                    format!("The file exceeds the limit of {}, {written} bytes were written", program_config.max_file_size)
+            ));
+        }
+        // Keep the session alive during a long upload
+        touch_session(&session_channel);
+        let offset = start_offset + written;
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&chunk);
+        }
+        let chunk_len = chunk.len() as u64;
+        let request = RpcRequest {
+            path: path.clone(),
+            method: "write".into(),
+            param: Some(vec![RpcValue::from(offset as i64), RpcValue::from(chunk)].into()),
+            timeout: None,
+        };
+        exec_rpc_request(&command_channel, &dir_cache, request, program_config)
+            .await
+            .map_err(|(status, Json(mut error))| {
+                error.detail = format!("Cannot write at offset {offset} after writing {written} bytes: {}", error.detail);
+                (status, Json(error))
+            })?;
+        written += chunk_len;
+    }
+
+    let mut report = FileUploadReport { written, crc: None, sha1: None };
+    let checksum_range = RpcValue::from(vec![RpcValue::from(start_offset as i64), RpcValue::from(written as i64)]);
+    let checksum_request = |method: &str| RpcRequest {
+        path: path.clone(),
+        method: method.into(),
+        param: Some(checksum_range.clone()),
+        timeout: None,
+    };
+    let checksum_mismatch = |checksum: &str, expected: &str, actual: &str| err_response(
+        Status::BadGateway,
+        format!("{checksum} of the written {written} bytes does not match, expected: {expected}, file node: {actual}")
+    );
+    match hasher {
+        Some(FileHasher::Crc(digest)) => {
+            let expected = digest.finalize();
+            let actual = exec_rpc_request(&command_channel, &dir_cache, checksum_request("crc"), program_config).await?;
+            if actual.as_u64() != u64::from(expected) {
+                return Err(checksum_mismatch("CRC", &format!("{expected:08x}"), &actual.to_cpon()));
+            }
+            report.crc = Some(expected);
+        }
+        Some(FileHasher::Sha1(hasher)) => {
+            let expected = sha1::Digest::finalize(hasher);
+            let actual = exec_rpc_request(&command_channel, &dir_cache, checksum_request("sha1"), program_config).await?;
+            let to_hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{byte:02x}")).collect::<String>();
+            if actual.as_blob() != expected.as_slice() {
+                return Err(checksum_mismatch("SHA1", &to_hex(&expected), &actual.to_cpon()));
+            }
+            report.sha1 = Some(to_hex(&expected));
+        }
+        None => { }
+    }
+    Ok(Json(report))
+}
+
+/// Gateway-wide allow and deny rules on the SHV RIs that can be called and subscribed
+#[derive(Clone)]
+struct Policy {
+    allow: Option<Arc<[PolicyRule]>>,
+    deny: Arc<[PolicyRule]>,
+}
+
+/// A rule of the policy with its `<path>:<method>` part, against which the
+/// method calls are matched
+struct PolicyRule {
+    rule: Glob,
+    call_rule: Glob,
+}
+
+impl PolicyRule {
+    fn parse(rule: &str) -> Result<Self, String> {
+        let invalid_rule = |e| format!("Invalid rule `{rule}`: {e}");
+        let glob = Glob::try_from(rule).map_err(invalid_rule)?;
+        let call_rule = Glob::try_from(format!("{}:{}", glob.path_str(), glob.method_str()).as_str()).map_err(invalid_rule)?;
+        Ok(Self { rule: glob, call_rule })
+    }
+
+    fn matches(&self, shv_ri: &ShvRI) -> bool {
+        if shv_ri.signal().is_none() {
+            self.call_rule.match_shv_ri(shv_ri)
+        } else {
+            self.rule.match_shv_ri(shv_ri)
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        self.rule.as_str()
+    }
+}
+
+/// The content of the `--policy` file
+#[derive(Deserialize)]
+struct PolicyFile {
+    allow: Option<Vec<String>>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+impl Policy {
+    fn from_json(json: &str) -> Result<Self, String> {
+        let PolicyFile { allow, deny } = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let parse_rules = |rules: Vec<String>| rules
+            .iter()
+            .map(|rule| PolicyRule::parse(rule))
+            .collect::<Result<Arc<[_]>, _>>();
+        Ok(Self {
+            allow: allow.map(parse_rules).transpose()?,
+            deny: parse_rules(deny)?,
+        })
+    }
+
+    /// Checks `shv_ri` against the rules, the deny rules take precedence
+    fn check(&self, shv_ri: &ShvRI) -> Result<(), String> {
+        if let Some(rule) = self.deny.iter().find(|rule| rule.matches(shv_ri)) {
+            return Err(format!("`{shv_ri}` is denied by the gateway policy rule `{}`", rule.as_str()));
+        }
+        if let Some(allow) = &self.allow && !allow.iter().any(|rule| rule.matches(shv_ri)) {
+            return Err(format!("`{shv_ri}` is not allowed by any gateway policy rule"));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn rules(rules: &[PolicyRule]) -> Vec<&str> {
+            rules.iter().map(PolicyRule::as_str).collect()
+        }
+        f.debug_struct("Policy")
+            .field("allow", &self.allow.as_deref().map(rules))
+            .field("deny", &rules(&self.deny))
+            .finish()
+    }
+}
+
+fn parse_policy_file(path: &str) -> Result<Policy, String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("Cannot read `{path}`: {e}"))?;
+    Policy::from_json(&json)
+}
+
+fn check_policy(policy: Option<&Policy>, shv_ri: &ShvRI) -> Result<(), ErrorResponse> {
+    policy
+        .map_or(Ok(()), |policy| policy.check(shv_ri))
+        .map_err(|e| err_response(Status::Forbidden, e))
+}
+
+fn check_call_policy(program_config: &ProgramConfig, path: &str, method: &str) -> Result<(), ErrorResponse> {
+    let Some(policy) = &program_config.policy else {
+        return Ok(());
+    };
+    let shv_ri = ShvRI::from_path_method_signal(path, method, None)
+        .map_err(|e| err_response(Status::BadRequest, format!("Invalid path `{path}` or method `{method}`: {e}")))?;
+    check_policy(Some(policy), &shv_ri)
+}
+
+/// Notifications matching a wildcard subscription can come from SHV RIs denied by the policy
+fn notification_permitted(policy: Option<&Policy>, msg: &RpcMessage) -> bool {
+    let Some(policy) = policy else {
+        return true;
+    };
+    let path = msg.shv_path().unwrap_or_default();
+    let source = msg.source().unwrap_or("get");
+    let signal = msg.method().unwrap_or_default();
+    match ShvRI::from_path_method_signal(path, source, Some(signal)) {
+        Ok(shv_ri) => policy.check(&shv_ri).inspect_err(|e| debug!("Dropping notification: {e}")).is_ok(),
+        Err(e) => {
+            warn!("Dropping notification with invalid SHV RI: {e}");
+            false
+        }
+    }
+}
+
+struct Session(String, SessionData);
+
+async fn find_session(req: &Request<'_>, session_id: &str) -> rocket::request::Outcome<Session, ErrorResponse> {
+    use rocket::request::Outcome;
+    let Sessions(sessions) = req.rocket().state().expect("Sessions are present");
+    let Some(session_data) = sessions.read().await.get(session_id).cloned() else {
+        return_err!(req, Status::Unauthorized, "Invalid session token");
+    };
+
+    Outcome::Success(Session(session_id.into(), session_data))
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Session {
+    type Error = ErrorResponse;
+
+
+    async fn from_request(req: &'r Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
+        use rocket::request::Outcome;
+        let value = req
+            .headers()
+            .get_one("Authorization");
+        let Some(session_id) = value else {
+            return_err!(req, Status::BadRequest, "Missing Authorization header");
+        };
+
+        let session = find_session(req, session_id).await;
+        if let Outcome::Success(Session(_, SessionData { session_channel, .. })) = &session {
+            touch_session(session_channel);
+        }
+        session
+    }
+}
+
+/// Resets the session timeout
+fn touch_session(session_channel: &UnboundedSender<SessionEvent>) {
+    session_channel
+        .unbounded_send(SessionEvent::Activity)
+        .unwrap_or_else(|e| error!("Cannot send SessionEvent::Activity: {e}"));
+}
+
+/// Session guard for the `GET` event streams consumed by the browser
+/// `EventSource` API. Besides the `Authorization` header, the session token is
+/// taken from the `session_id` cookie or, as the last resort, from the
+/// `session_id` query parameter, which ends up in the access logs.
+struct EventSourceSession(Session);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for EventSourceSession {
+    type Error = ErrorResponse;
+
+    async fn from_request(req: &'r Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
+        use rocket::request::Outcome;
+        let session_id = req
+            .headers()
+            .get_one("Authorization")
+            .or_else(|| req.cookies().get("session_id").map(|cookie| cookie.value()))
+            .or_else(|| req.query_value::<&str>("session_id").and_then(Result::ok));
+        let Some(session_id) = session_id else {
+            return_err!(req, Status::BadRequest, "Missing session token");
+        };
+
+        find_session(req, session_id).await.map(EventSourceSession)
+    }
+}
+
+#[catch(default)]
+fn catch_default(status: Status, req: &Request) -> ErrorResponse {
+    req.local_cache(|| err_response::<&str>(status, status.reason())).clone()
+}
+
+struct Random(pub(crate) Arc<Mutex<ChaCha20Rng>>);
+
+#[derive(Debug, clap::Parser)]
+struct ProgramConfig {
+    #[arg(long)]
+    broker_url: Url,
+    #[arg(long, default_value = "10")]
+    max_user_sessions: i32,
+    #[arg(long, default_value = "10m", value_parser = |val: &str| duration_str::parse_std(val))]
+    session_timeout: Duration,
+    #[arg(long, default_value = "60s", value_parser = |val: &str| duration_str::parse_std(val))]
+    heartbeat_interval: Duration,
+    #[arg(long, default_value = "1000")]
+    notification_history_size: usize,
+    #[arg(long, default_value = "30s", value_parser = |val: &str| duration_str::parse_std(val))]
+    subscribe_resume_timeout: Duration,
+    #[arg(long, value_parser = |val: &str| duration_str::parse_std(val))]
+    reconnect_interval: Option<Duration>,
+    #[arg(long, value_parser = |val: &str| duration_str::parse_std(val))]
+    rpc_timeout: Option<Duration>,
+    #[arg(long, default_value = "10s", value_parser = |val: &str| duration_str::parse_std(val))]
+    rpc_keepalive_interval: Duration,
+    #[arg(long, value_parser = parse_rpc_error_status_override)]
+    rpc_error_status: Vec<RpcErrorStatusOverride>,
+    #[arg(long)]
+    rpc_error_status_compat: bool,
+    #[arg(long)]
+    coerce_params: bool,
+    #[arg(long, default_value = "5m", value_parser = |val: &str| duration_str::parse_std(val))]
+    dir_cache_ttl: Duration,
+    #[arg(long)]
+    read_only: bool,
+    #[arg(long, default_value = "100")]
+    max_session_jobs: usize,
+    #[arg(long, default_value = "1h", value_parser = |val: &str| duration_str::parse_std(val))]
+    job_retention: Duration,
+    #[arg(long, default_value = "65536")]
+    file_chunk_size: u64,
+    #[arg(long, default_value = "1GiB", value_parser = |val: &str| val.parse::<rocket::data::ByteUnit>().map_err(|e| e.to_string()))]
+    max_file_size: rocket::data::ByteUnit,
+    #[arg(long, value_parser = parse_policy_file)]
+    policy: Option<Policy>,
+    #[arg(long, default_value = "30s", value_parser = |val: &str| duration_str::parse_std(val))]
+    device_request_timeout: Duration,
+    #[arg(long, default_value = "100")]
+    device_requests_capacity: usize,
+    #[arg(long, default_value = "16")]
+    tree_max_depth: usize,
+    #[arg(long, default_value = "10000")]
+    tree_max_nodes: usize,
+    #[arg(long, default_value = "8")]
+    tree_concurrency: usize,
+    #[arg(long, default_value = "100")]
+    max_batch_size: usize,
+    #[arg(long, default_value = "8")]
+    batch_concurrency: usize,
     #[arg(short = 'v', long = "verbose")]
     verbose: Option<String>,
     #[arg(short = 'V', long = "version")]
@@ -507,7 +3725,7 @@ pub(crate) fn build_rocket(program_config: ProgramConfig) -> Rocket<Build> {
     let cors = CorsOptions::default()
         .allowed_origins(AllowedOrigins::all())
         .allowed_methods(
-            [rocket::http::Method::Post]
+            [rocket::http::Method::Get, rocket::http::Method::Post, rocket::http::Method::Put, rocket::http::Method::Delete]
             .into_iter()
             .map(From::from)
             .collect(),
@@ -521,9 +3739,38 @@ pub(crate) fn build_rocket(program_config: ProgramConfig) -> Rocket<Build> {
             ..Default::default()
         })
         .attach(cors.to_cors().expect("Cannot set CORS policy"))
-        .mount("/api", routes![api_login, api_logout, api_rpc, api_subscribe])
+        .mount("/api", routes![
+            api_login,
+            api_logout,
+            api_rpc,
+            api_rpc_cancel,
+            api_rpc_stream,
+            api_rpc_batch,
+            api_rpc_apply,
+            api_jobs_start,
+            api_jobs,
+            api_job,
+            api_job_remove,
+            api_device_requests,
+            api_device_respond,
+            api_device_signal,
+            api_value_get,
+            api_value_put,
+            api_ls,
+            api_dir,
+            api_tree,
+            api_values,
+            api_file_get,
+            api_file_put,
+            api_subscribe,
+            api_subscribe_get,
+            api_notifications,
+            api_subscriptions,
+            api_subscriptions_add,
+            api_subscriptions_remove,
+        ])
         .register("/", catchers![catch_default])
-        .manage(program_config)
+        .manage(Arc::new(program_config))
         .manage(Sessions::default())
         .manage(Random(Arc::new(Mutex::new(from_os_rng()))));
 
@@ -19,11 +19,12 @@ use shvclient::clientapi::{CallRpcMethodError, CallRpcMethodErrorKind};
 use shvclient::{ClientCommandSender, ClientEventsReceiver};
 use shvproto::RpcValue;
 use shvrpc::client::ClientConfig;
+use shvrpc::rpc::ShvRI;
 use shvrpc::rpcmessage::RpcError;
 use tokio_util::compat::TokioAsyncReadCompatExt;
 use url::Url;
 
-use crate::{build_rocket, ErrorResponseBody, LoginResponse, ProgramConfig, SubscribeEvent};
+use crate::{build_rocket, ErrorResponseBody, FileUploadReport, LoginResponse, Policy, ProgramConfig, RpcErrorInfo, SessionEventLog, SessionEventReplay, SessionNotification, SessionStreamEvent, SubscribeEvent, SubscriptionInfo};
 
 const BROKER_ADDRESS: &str = "127.0.0.1:37567";
 const BROKER_URL: &str = formatcp!("tcp://{BROKER_ADDRESS}");
@@ -61,6 +62,37 @@ async fn start_broker() {
     panic!("Could not start the broker");
 }
 
+static SETPOINT: LazyLock<std::sync::Mutex<RpcValue>> = LazyLock::new(|| std::sync::Mutex::new(RpcValue::from(0)));
+static FILE_CONTENT: LazyLock<Vec<u8>> = LazyLock::new(|| (0..1000).map(|i| (i % 251) as u8).collect());
+
+static UPLOADED_CONTENT: LazyLock<std::sync::Mutex<Vec<u8>>> = LazyLock::new(Default::default);
+
+fn file_stat(size: usize) -> RpcValue {
+    std::collections::BTreeMap::from([(0, RpcValue::from(0)), (1, RpcValue::from(size as i64)), (2, RpcValue::from(128))]).into()
+}
+
+fn file_write(content: &mut Vec<u8>, param: &[RpcValue]) -> Result<RpcValue, RpcError> {
+    let [offset, data] = param else {
+        return Err(RpcError::new(shvrpc::rpcmessage::RpcErrorCode::InvalidParam, "Expected [offset, data]"));
+    };
+    let offset = offset.as_i64() as usize;
+    let data = data.as_blob();
+    if content.len() < offset + data.len() {
+        content.resize(offset + data.len(), 0);
+    }
+    content[offset..offset + data.len()].copy_from_slice(data);
+    Ok(RpcValue::null())
+}
+
+fn file_read(content: &[u8], param: &[i64]) -> Result<RpcValue, RpcError> {
+    let &[offset, size] = param else {
+        return Err(RpcError::new(shvrpc::rpcmessage::RpcErrorCode::InvalidParam, "Expected [offset, size]"));
+    };
+    let start = (offset as usize).min(content.len());
+    let end = (start + size as usize).min(content.len());
+    Ok(content[start..end].to_vec().into())
+}
+
 async fn start_testing_client() -> Option<(ClientCommandSender, ClientEventsReceiver)> {
     let (tx, rx) = rocket::futures::channel::oneshot::channel();
     tokio::spawn(async {
@@ -72,15 +104,104 @@ async fn start_testing_client() -> Option<(ClientCommandSender, ClientEventsRece
             reconnect_interval: None,
         };
         let value_node = shvclient::static_node! {
-            ValueNode(request, _tx) {
+            ValueNode(request, client_cmd_tx) {
                 "echo" [IsGetter, Read, "", ""] (param: RpcValue) => {
                     Some(Ok(param))
                 }
+                "sleep" [, Write, "Int", "Null"] (millis: i64) => {
+                    tokio::time::sleep(Duration::from_millis(millis as u64)).await;
+                    Some(Ok(RpcValue::null()))
+                }
+                "progress" [, Write, "Int", "Null"] (steps: i64) => {
+                    for step in 0..steps {
+                        let mut delay = request.prepare_response().unwrap();
+                        delay.set_delay(step as f64 / steps as f64);
+                        client_cmd_tx.send_message(delay).unwrap_or_else(|_| error!("Cannot send a delay response"));
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                    Some(Ok(RpcValue::null()))
+                }
+                "echoUInt" [, Read, "UInt", "UInt"] (param: RpcValue) => {
+                    Some(Ok(param))
+                }
+                "echoDecimal" [, Read, "Decimal|Null", "Decimal"] (param: RpcValue) => {
+                    Some(Ok(param))
+                }
+            }
+        };
+        let setpoint_node = shvclient::static_node! {
+            SetpointNode(request, _tx) {
+                "get" [IsGetter, Read, "", "Int"] => {
+                    Some(Ok(SETPOINT.lock().unwrap().clone()))
+                }
+                "set" [IsSetter, Write, "Int", ""] (value: i64) => {
+                    *SETPOINT.lock().unwrap() = RpcValue::from(value);
+                    Some(Ok(RpcValue::null()))
+                }
+            }
+        };
+        let faulty_node = shvclient::static_node! {
+            FaultyNode(request, _tx) {
+                "get" [IsGetter, Read, "", "Int"] => {
+                    Some(Err(RpcError::new(shvrpc::rpcmessage::RpcErrorCode::MethodCallException, "Sensor failure")))
+                }
+            }
+        };
+        let file_node = shvclient::static_node! {
+            FileNode(request, _tx) {
+                "stat" [, Read, "", "iMap"] => {
+                    Some(Ok(file_stat(FILE_CONTENT.len())))
+                }
+                "read" [, Read, "[Int, Int]", "Blob"] (param: Vec<i64>) => {
+                    Some(file_read(&FILE_CONTENT, &param))
+                }
+            }
+        };
+        let upload_node = shvclient::static_node! {
+            UploadNode(request, _tx) {
+                "stat" [, Read, "", "iMap"] => {
+                    let size = UPLOADED_CONTENT.lock().unwrap().len();
+                    let mut stat = file_stat(size);
+                    // maxWrite
+                    if let shvproto::Value::IMap(stat) = &mut stat.value {
+                        stat.insert(5, RpcValue::from(100));
+                    }
+                    Some(Ok(stat))
+                }
+                "write" [, Write, "[Int, Blob]", ""] (param: Vec<RpcValue>) => {
+                    Some(file_write(&mut UPLOADED_CONTENT.lock().unwrap(), &param))
+                }
+                "crc" [, Read, "[Int, Int]", "UInt"] (param: Vec<i64>) => {
+                    let data = file_read(&UPLOADED_CONTENT.lock().unwrap(), &param).map(|data| data.as_blob().to_vec());
+                    Some(data.map(|data| RpcValue::from(crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(&data) as u64)))
+                }
+                "sha1" [, Read, "[Int, Int]", "Blob"] (param: Vec<i64>) => {
+                    let data = file_read(&UPLOADED_CONTENT.lock().unwrap(), &param).map(|data| data.as_blob().to_vec());
+                    Some(data.map(|data| RpcValue::from(<sha1::Sha1 as sha1::Digest>::digest(&data).to_vec())))
+                }
+            }
+        };
+        let lossy_upload_node = shvclient::static_node! {
+            LossyUploadNode(request, _tx) {
+                "stat" [, Read, "", "iMap"] => {
+                    Some(Ok(file_stat(0)))
+                }
+                "write" [, Write, "[Int, Blob]", ""] => {
+                    Some(Ok(RpcValue::null()))
+                }
+                "crc" [, Read, "[Int, Int]", "UInt"] => {
+                    Some(Ok(RpcValue::from(0_u64)))
+                }
             }
         };
         shvclient::Client::new()
             .app(DotAppNode::new("testing_client"))
             .mount_static("value", value_node)
+            .mount_static("setpoint 1", setpoint_node)
+            .mount_static("faulty", faulty_node)
+            .mount_static("file", file_node)
+            .mount_static("upload", upload_node)
+            .mount_static("lossy upload", lossy_upload_node)
             .run_with_init(&client_config, |commands_tx, events_rx| {
                 {
                     let commands_tx = commands_tx.clone();
@@ -154,6 +275,28 @@ fn program_config() -> ProgramConfig {
         max_user_sessions: 10,
         session_timeout: Duration::from_secs(60),
         heartbeat_interval: Duration::from_secs(60),
+        notification_history_size: 1000,
+        subscribe_resume_timeout: Duration::from_secs(5),
+        reconnect_interval: None,
+        rpc_timeout: None,
+        rpc_keepalive_interval: Duration::from_millis(100),
+        rpc_error_status: vec![],
+        rpc_error_status_compat: false,
+        coerce_params: false,
+        dir_cache_ttl: Duration::from_secs(60),
+        read_only: false,
+        max_session_jobs: 100,
+        job_retention: Duration::from_secs(60),
+        file_chunk_size: 65536,
+        max_file_size: rocket::data::ByteUnit::Gibibyte(1),
+        policy: None,
+        device_request_timeout: Duration::from_secs(1),
+        device_requests_capacity: 100,
+        tree_max_depth: 16,
+        tree_max_nodes: 10000,
+        tree_concurrency: 8,
+        max_batch_size: 100,
+        batch_concurrency: 8,
         verbose: None,
         version: false,
     }
@@ -170,6 +313,10 @@ fn err_response() {
 
 #[test]
 fn err_rpc_response() {
+    let compat_config = ProgramConfig {
+        rpc_error_status_compat: true,
+        ..program_config()
+    };
     {
         let (error_status, error_body) = crate::err_response_rpc_call(
             CallRpcMethodError::new(
@@ -179,7 +326,8 @@ fn err_rpc_response() {
                         shvrpc::rpcmessage::RpcErrorCode::MethodNotFound,
                         "Unknown method")
                 )
-            )
+            ),
+            &compat_config,
         );
         assert_eq!(error_status, Status::InternalServerError);
         let body: ErrorResponseBody = serde_json::from_str(&serde_json::to_string(&error_body.0).unwrap()).unwrap();
@@ -191,7 +339,8 @@ fn err_rpc_response() {
             CallRpcMethodError::new(
                 "foo/bar",
                 "baz",
-                CallRpcMethodErrorKind::ConnectionClosed)
+                CallRpcMethodErrorKind::ConnectionClosed),
+            &compat_config,
         );
         assert_eq!(error_status, Status::InternalServerError);
         let body: ErrorResponseBody = serde_json::from_str(&serde_json::to_string(&error_body.0).unwrap()).unwrap();
@@ -200,6 +349,118 @@ fn err_rpc_response() {
     }
 }
 
+#[test]
+fn err_rpc_response_status() {
+    use shvrpc::rpcmessage::RpcErrorCode;
+    let rpc_error = |code: RpcErrorCode| CallRpcMethodError::new(
+        "foo/bar",
+        "baz",
+        CallRpcMethodErrorKind::RpcError(RpcError::new(code, "error"))
+    );
+    let status_of = |error: CallRpcMethodError, config: &ProgramConfig| crate::err_response_rpc_call(error, config).0;
+
+    let config = program_config();
+    assert_eq!(status_of(rpc_error(RpcErrorCode::MethodNotFound), &config), Status::NotFound);
+    assert_eq!(status_of(rpc_error(RpcErrorCode::PermissionDenied), &config), Status::Forbidden);
+    assert_eq!(status_of(rpc_error(RpcErrorCode::InvalidParam), &config), Status::BadRequest);
+    assert_eq!(status_of(rpc_error(RpcErrorCode::MethodCallTimeout), &config), Status::GatewayTimeout);
+    assert_eq!(status_of(rpc_error(RpcErrorCode::MethodCallException), &config), Status::InternalServerError);
+    assert_eq!(
+        status_of(CallRpcMethodError::new("foo/bar", "baz", CallRpcMethodErrorKind::ConnectionClosed), &config),
+        Status::ServiceUnavailable
+    );
+
+    let (error_status, error_body) = crate::err_response_rpc_call(rpc_error(RpcErrorCode::MethodNotFound), &config);
+    assert_eq!(error_body.code, error_status.code);
+    assert_eq!(error_body.shv_error, Some("RpcError(MethodNotFound)".into()));
+
+    let config = ProgramConfig {
+        rpc_error_status: ["MethodNotFound=410", "UserError=422"]
+            .into_iter()
+            .map(|val| crate::parse_rpc_error_status_override(val).unwrap())
+            .collect(),
+        ..program_config()
+    };
+    assert_eq!(status_of(rpc_error(RpcErrorCode::MethodNotFound), &config), Status::Gone);
+    assert_eq!(status_of(rpc_error(RpcErrorCode::PermissionDenied), &config), Status::Forbidden);
+    assert_eq!(
+        status_of(CallRpcMethodError::new("foo/bar", "baz", CallRpcMethodErrorKind::RpcError(RpcError::new(42u32, "error"))), &config),
+        Status::UnprocessableEntity
+    );
+
+    assert!(crate::parse_rpc_error_status_override("NoSuchError=404").is_err());
+    assert!(crate::parse_rpc_error_status_override("MethodNotFound=200").is_err());
+    assert!(crate::parse_rpc_error_status_override("MethodNotFound").is_err());
+}
+
+#[test]
+fn err_rpc_response_structured() {
+    let body_of = |kind: CallRpcMethodErrorKind| {
+        let (_, error_body) = crate::err_response_rpc_call(CallRpcMethodError::new("foo/bar", "baz", kind), &program_config());
+        serde_json::from_str::<ErrorResponseBody>(&serde_json::to_string(&error_body.0).unwrap()).unwrap()
+    };
+
+    let body = body_of(CallRpcMethodErrorKind::RpcError(RpcError::new(shvrpc::rpcmessage::RpcErrorCode::MethodNotFound, "Unknown method")));
+    assert_eq!(body.shv_error, Some("RpcError(MethodNotFound)".into()));
+    assert_eq!(body.rpc_error.as_deref(), Some(&RpcErrorInfo {
+        code: Some(shvrpc::rpcmessage::RpcErrorCode::MethodNotFound as u32),
+        name: "MethodNotFound".into(),
+        message: "Unknown method".into(),
+        path: "foo/bar".into(),
+        method: "baz".into(),
+    }));
+
+    let body = body_of(CallRpcMethodErrorKind::RpcError(RpcError::new(1042u32, "Device is busy")));
+    assert_eq!(body.rpc_error.as_deref(), Some(&RpcErrorInfo {
+        code: Some(1042),
+        name: "UserError".into(),
+        message: "Device is busy".into(),
+        path: "foo/bar".into(),
+        method: "baz".into(),
+    }));
+
+    let body = body_of(CallRpcMethodErrorKind::ConnectionClosed);
+    assert_eq!(body.rpc_error.as_deref(), Some(&RpcErrorInfo {
+        code: None,
+        name: "ConnectionClosed".into(),
+        message: "Connection closed".into(),
+        path: "foo/bar".into(),
+        method: "baz".into(),
+    }));
+
+    let (_, error_body) = crate::err_response(Status::BadRequest, "Invalid input");
+    assert!(!serde_json::to_string(&error_body.0).unwrap().contains("rpc_error"));
+}
+
+#[tokio::test]
+async fn session_event_log_replay() {
+    let event_log = SessionEventLog::new(3);
+    for i in 1..=5 {
+        event_log.push(SessionStreamEvent::Error(format!("event {i}")));
+    }
+    let replayed_ids = |replay: &SessionEventReplay| replay.events.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+
+    let (replay, _) = event_log.subscribe(None);
+    assert_eq!(replay.lost_count, 0);
+    assert!(replay.events.is_empty());
+
+    let (replay, _) = event_log.subscribe(Some(3));
+    assert_eq!(replay.lost_count, 0);
+    assert_eq!(replayed_ids(&replay), [4, 5]);
+
+    let (replay, _) = event_log.subscribe(Some(1));
+    assert_eq!(replay.lost_count, 1);
+    assert_eq!(replayed_ids(&replay), [3, 4, 5]);
+
+    let (replay, _) = event_log.subscribe(Some(5));
+    assert_eq!(replay.lost_count, 0);
+    assert!(replay.events.is_empty());
+
+    let (_, mut events_rx) = event_log.subscribe(Some(5));
+    event_log.push(SessionStreamEvent::Error("event 6".into()));
+    assert_eq!(events_rx.recv().await.unwrap().0, 6);
+}
+
 #[tokio::test]
 async fn api_login_invalid_request() {
     let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
@@ -246,6 +507,7 @@ fn api_login_fails() {
             code: Status::Unauthorized.code,
             detail: "Bad credentials".into(),
             shv_error: None,
+            rpc_error: None,
         },
         resp.into_json::<ErrorResponseBody>().await.unwrap());
     });
@@ -372,6 +634,16 @@ fn api_rpc_calls() {
             RpcValue::from("test"),
             RpcValue::from(shvproto::make_imap!(1 => "foo", 2 => 42))
         ];
+        {
+            let resp = rpc_call_dispatcher.call("test/device/value", "nonexistent", None::<RpcValue>).await;
+            assert_eq!(resp.status(), Status::NotFound);
+            let rpc_error = resp.into_json::<ErrorResponseBody>().await.unwrap().rpc_error.unwrap();
+            assert_eq!(rpc_error.code, Some(shvrpc::rpcmessage::RpcErrorCode::MethodNotFound as u32));
+            assert_eq!(rpc_error.name, "MethodNotFound");
+            assert_eq!(rpc_error.path, "test/device/value");
+            assert_eq!(rpc_error.method, "nonexistent");
+        }
+
         for arg in &values {
             let resp = rpc_call_dispatcher.call("test/device/value", "echo", Some(arg)).await;
             assert_eq!(resp.status(), Status::Ok);
@@ -436,7 +708,8 @@ fn api_subscribe() {
                         panic!("Unexpected event");
                     };
                     info!("{data}");
-                    assert!(id.is_none());
+                    let (_stream_id, event_id) = id.as_deref().and_then(|id| id.split_once('.')).expect("Event ID");
+                    assert_eq!(event_id, (i + 1).to_string());
                     assert_eq!(event, "message");
                     let parsed_data: SubscribeEvent = RpcValue::from_json(&data).unwrap().try_into().unwrap();
                     assert_eq!(parsed_data.path, Some("test/device/value".into()));
@@ -449,3 +722,2129 @@ fn api_subscribe() {
         join_all(tasks).await;
     });
 }
+
+#[test]
+fn api_subscribe_get() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+
+        // Missing session token
+        {
+            let resp = client
+                .get("/api/subscribe?shv_ri=test/device/value:*:*")
+                .dispatch()
+                .await;
+            assert_eq!(resp.status(), Status::BadRequest);
+        }
+
+        // Missing shv_ri
+        {
+            let resp = client
+                .get(format!("/api/subscribe?session_id={session_id}"))
+                .dispatch()
+                .await;
+            assert_eq!(resp.status(), Status::UnprocessableEntity);
+        }
+
+        // The cookie takes precedence over the query parameter
+        {
+            let resp = client
+                .get("/api/subscribe?session_id=invalid")
+                .cookie(rocket::http::Cookie::new("session_id", session_id.clone()))
+                .dispatch()
+                .await;
+            assert_eq!(resp.status(), Status::UnprocessableEntity);
+        }
+
+        // The query parameter is accepted only by the event streams
+        {
+            let resp = client
+                .post(format!("/api/rpc?session_id={session_id}"))
+                .header(ContentType::JSON)
+                .body(r#"{"path": "test/device/value", "method": "echo"}"#)
+                .dispatch()
+                .await;
+            assert_eq!(resp.status(), Status::BadRequest);
+        }
+
+        let requests = [
+            client.get(format!("/api/subscribe?session_id={session_id}&shv_ri=test/device/value:*:event&shv_ri=test/device/value:*:chng")),
+            client.get("/api/subscribe?shv_ri=test/*:*:*").cookie(rocket::http::Cookie::new("session_id", session_id.clone())),
+        ];
+        for req in requests {
+            let resp = req.dispatch().await;
+            assert_eq!(resp.status(), Status::Ok);
+            assert!(resp.content_type().unwrap().is_event_stream());
+
+            let mut reader = sse_codec::decode_stream(tokio::io::BufReader::new(resp).compat());
+            for _ in 0..3 {
+                let event = reader
+                    .next()
+                    .await
+                    .expect("Unexpected end of stream")
+                    .unwrap_or_else(|e| panic!("Unexpected error in event stream: {e}"));
+                let sse_codec::Event::Message{ event, data, .. } = event else {
+                    panic!("Unexpected event");
+                };
+                assert_eq!(event, "message");
+                let parsed_data: SubscribeEvent = RpcValue::from_json(&data).unwrap().try_into().unwrap();
+                assert_eq!(parsed_data.path, Some("test/device/value".into()));
+                assert_eq!(parsed_data.signal, Some("event".into()));
+                assert_eq!(parsed_data.param, Some(RpcValue::from(42)));
+            }
+        }
+
+        // A reconnecting client receives the notifications missed in the meantime
+        let read_event = async |reader: &mut (dyn rocket::futures::Stream<Item = Result<sse_codec::Event, sse_codec::Error>> + Unpin)| {
+            let event = reader
+                .next()
+                .await
+                .expect("Unexpected end of stream")
+                .unwrap_or_else(|e| panic!("Unexpected error in event stream: {e}"));
+            let sse_codec::Event::Message{ id, event, data } = event else {
+                panic!("Unexpected event");
+            };
+            (id, event, data)
+        };
+        let resp = client
+            .get(format!("/api/subscribe?session_id={session_id}&shv_ri=test/device/value:*:event"))
+            .dispatch()
+            .await;
+        let mut reader = sse_codec::decode_stream(tokio::io::BufReader::new(resp).compat());
+        let (last_event_id, ..) = read_event(&mut reader).await;
+        let last_event_id = last_event_id.unwrap();
+        drop(reader);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let resp = client
+            .get(format!("/api/subscribe?session_id={session_id}&shv_ri=test/device/value:*:event"))
+            .header(rocket::http::Header::new("Last-Event-ID", last_event_id.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let mut reader = sse_codec::decode_stream(tokio::io::BufReader::new(resp).compat());
+        let (stream_id, event_id) = last_event_id.split_once('.').unwrap();
+        let event_id = event_id.parse::<u64>().unwrap();
+        for next_event_id in event_id + 1..event_id + 4 {
+            let (id, event, _) = read_event(&mut reader).await;
+            assert_eq!(event, "message");
+            assert_eq!(id.unwrap(), format!("{stream_id}.{next_event_id}"));
+        }
+        drop(reader);
+
+        // A stream that is not kept cannot be resumed
+        let resp = client
+            .get(format!("/api/subscribe?session_id={session_id}&shv_ri=test/device/value:*:event"))
+            .header(rocket::http::Header::new("Last-Event-ID", "999.1"))
+            .dispatch()
+            .await;
+        let mut reader = sse_codec::decode_stream(tokio::io::BufReader::new(resp).compat());
+        let (_, event, data) = read_event(&mut reader).await;
+        assert_eq!(event, "error");
+        assert_eq!(data, "Stream 999 cannot be resumed, its notifications are lost");
+        let (id, event, _) = read_event(&mut reader).await;
+        assert_eq!(event, "message");
+        assert!(id.unwrap().ends_with(".1"));
+    });
+}
+
+#[test]
+fn api_notifications() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let auth_header = || rocket::http::Header::new("Authorization", session_id.clone());
+
+        let resp = client
+            .get(format!("/api/notifications?session_id={session_id}"))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        assert!(resp.content_type().unwrap().is_event_stream());
+        let mut reader = sse_codec::decode_stream(tokio::io::BufReader::new(resp).compat());
+
+        let mut subscription_ids = vec![];
+        for shv_ri in ["test/device/value:*:event", "test/*:*:*"] {
+            let resp = client
+                .post("/api/subscriptions")
+                .header(ContentType::JSON)
+                .header(auth_header())
+                .body(format!(r#"{{"shv_ri": "{shv_ri}"}}"#))
+                .dispatch()
+                .await;
+            assert_eq!(resp.status(), Status::Ok);
+            let info = resp.into_json::<SubscriptionInfo>().await.unwrap();
+            assert_eq!(info.shv_ri, shv_ri);
+            subscription_ids.push(info.subscription_id);
+        }
+
+        let resp = client
+            .get("/api/subscriptions")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        let listed_ids = resp
+            .into_json::<Vec<SubscriptionInfo>>()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|info| info.subscription_id)
+            .collect::<Vec<_>>();
+        assert_eq!(listed_ids, subscription_ids);
+
+        let mut received_ids = std::collections::HashSet::new();
+        let mut last_event_id = 0;
+        for _ in 0..10 {
+            let event = reader
+                .next()
+                .await
+                .expect("Unexpected end of stream")
+                .unwrap_or_else(|e| panic!("Unexpected error in event stream: {e}"));
+            let sse_codec::Event::Message{ id, event, data } = event else {
+                panic!("Unexpected event");
+            };
+            assert_eq!(event, "message");
+            let event_id = id.expect("Event ID is set").parse::<u64>().unwrap();
+            assert!(event_id > last_event_id);
+            last_event_id = event_id;
+            let notification: SessionNotification = RpcValue::from_json(&data).unwrap().try_into().unwrap();
+            assert!(subscription_ids.contains(&notification.subscription_id));
+            assert_eq!(notification.path, Some("test/device/value".into()));
+            assert_eq!(notification.signal, Some("event".into()));
+            assert_eq!(notification.param, Some(RpcValue::from(42)));
+            received_ids.insert(notification.subscription_id);
+        }
+        assert_eq!(received_ids.len(), subscription_ids.len());
+        drop(reader);
+
+        // Resume the stream
+        let resume_from = last_event_id - 5;
+        let resp = client
+            .get("/api/notifications")
+            .header(auth_header())
+            .header(rocket::http::Header::new("Last-Event-ID", resume_from.to_string()))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let mut reader = sse_codec::decode_stream(tokio::io::BufReader::new(resp).compat());
+        for expected_id in resume_from + 1..=last_event_id + 5 {
+            let event = reader
+                .next()
+                .await
+                .expect("Unexpected end of stream")
+                .unwrap_or_else(|e| panic!("Unexpected error in event stream: {e}"));
+            let sse_codec::Event::Message{ id, event, .. } = event else {
+                panic!("Unexpected event");
+            };
+            assert_eq!(event, "message");
+            assert_eq!(id, Some(expected_id.to_string()));
+        }
+        drop(reader);
+
+        let resp = client
+            .delete(format!("/api/subscriptions/{}", subscription_ids[0]))
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+
+        let resp = client
+            .delete(format!("/api/subscriptions/{}", subscription_ids[0]))
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::NotFound);
+
+        let resp = client
+            .get("/api/subscriptions")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        let listed = resp.into_json::<Vec<SubscriptionInfo>>().await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].subscription_id, subscription_ids[1]);
+    });
+}
+
+#[test]
+fn api_broker_reconnect() {
+    shared_rt_test(async {
+        let config = ProgramConfig {
+            reconnect_interval: Some(Duration::from_secs(1)),
+            ..program_config()
+        };
+        let client = RocketClient::untracked(build_rocket(config)).await.unwrap();
+
+        let login = || async {
+            client
+                .post("/api/login")
+                .header(ContentType::JSON)
+                .body(r#"{"username": "admin", "password": "admin"}"#)
+                .dispatch()
+                .await
+                .into_json::<LoginResponse>()
+                .await
+                .unwrap()
+                .session_id
+        };
+        let rpc_call = |session_id: String, body: String| {
+            client
+                .post("/api/rpc")
+                .header(rocket::http::Header::new("Authorization", session_id))
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+        };
+        let session_id = login().await;
+        let admin_session_id = login().await;
+
+        let resp = rpc_call(session_id.clone(), r#"{"path": ".broker/currentClient", "method": "info"}"#.into()).await;
+        let client_info = resp.into_string().await.map(|s| RpcValue::from_json(&s)).unwrap().unwrap();
+        let client_id = client_info.as_map().get("clientId").expect("clientId is in the client info").as_int();
+
+        let resp = client
+            .post("/api/subscriptions")
+            .header(ContentType::JSON)
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .body(r#"{"shv_ri": "test/device/value:*:event"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+
+        let streams = [
+            client.get(format!("/api/notifications?session_id={session_id}")).dispatch().await,
+            client.get(format!("/api/subscribe?session_id={session_id}&shv_ri=test/device/value:*:event")).dispatch().await,
+        ];
+        let mut readers = streams
+            .into_iter()
+            .map(|resp| sse_codec::decode_stream(tokio::io::BufReader::new(resp).compat()))
+            .collect::<Vec<_>>();
+
+        async fn next_event_name(reader: &mut (impl rocket::futures::Stream<Item = Result<sse_codec::Event, sse_codec::Error>> + Unpin)) -> String {
+            let event = reader
+                .next()
+                .await
+                .expect("Unexpected end of stream")
+                .unwrap_or_else(|e| panic!("Unexpected error in event stream: {e}"));
+            let sse_codec::Event::Message{ event, .. } = event else {
+                panic!("Unexpected event");
+            };
+            event
+        }
+
+        for reader in &mut readers {
+            assert_eq!(next_event_name(reader).await, "message");
+        }
+
+        let resp = rpc_call(admin_session_id, format!(r#"{{"path": ".broker", "method": "disconnectClient", "param": {client_id}}}"#)).await;
+        assert_eq!(resp.status(), Status::Ok);
+
+        for reader in &mut readers {
+            let mut event = next_event_name(reader).await;
+            while event == "message" {
+                event = next_event_name(reader).await;
+            }
+            assert_eq!(event, "disconnected");
+        }
+        let resp = rpc_call(session_id.clone(), r#"{"path": ".broker", "method": "ls"}"#.into()).await;
+        assert_eq!(resp.status(), Status::ServiceUnavailable);
+
+        for reader in &mut readers {
+            assert_eq!(next_event_name(reader).await, "reconnected");
+            assert_eq!(next_event_name(reader).await, "message");
+        }
+        let resp = rpc_call(session_id, r#"{"path": ".broker", "method": "ls"}"#.into()).await;
+        assert_eq!(resp.status(), Status::Ok);
+    });
+}
+
+#[test]
+fn api_rpc_batch() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+
+        let body = r#"[
+            {"path": "test/device/value", "method": "echo", "param": 42},
+            {"path": "test/device/value", "method": "nonexistent"},
+            {"path": "test/device/value", "method": "echo", "param": "foo"}
+        ]"#;
+
+        let resp = client
+            .post("/api/rpc/batch")
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let items = resp.into_json::<Vec<serde_json::Value>>().await.unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0]["result"], serde_json::json!(42));
+        assert_eq!(items[1]["error"]["code"], serde_json::json!(Status::NotFound.code));
+        assert_eq!(items[1]["error"]["rpc_error"]["name"], serde_json::json!("MethodNotFound"));
+        assert_eq!(items[2]["result"], serde_json::json!("foo"));
+
+        let resp = client
+            .post("/api/rpc/batch?stop_on_error=true")
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let items = resp.into_json::<Vec<serde_json::Value>>().await.unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[1]["error"]["code"], serde_json::json!(Status::NotFound.code));
+        for (item, expected_result) in [(&items[0], serde_json::json!(42)), (&items[2], serde_json::json!("foo"))] {
+            assert!(
+                item["result"] == expected_result || item["error"]["code"] == serde_json::json!(Status::FailedDependency.code),
+                "Unexpected batch item: {item}"
+            );
+        }
+
+        let resp = client
+            .post("/api/rpc/batch")
+            .header(rocket::http::Header::new("Authorization", session_id))
+            .header(ContentType::JSON)
+            .body(r#"{"path": "test/device/value", "method": "echo"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::UnprocessableEntity);
+
+        let client = RocketClient::untracked(build_rocket(ProgramConfig {
+            max_batch_size: 2,
+            batch_concurrency: 1,
+            ..program_config()
+        })).await.unwrap();
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let batch = async |uri: &'static str, body: &'static str| client
+            .post(uri)
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await;
+
+        for uri in ["/api/rpc/batch", "/api/rpc/apply"] {
+            let resp = batch(uri, body).await;
+            assert_eq!(resp.status(), Status::PayloadTooLarge);
+            assert_eq!(resp.into_json::<ErrorResponseBody>().await.unwrap().detail, "The batch has 3 calls, at most 2 are allowed");
+        }
+
+        // The calls are executed one after another
+        let started = std::time::Instant::now();
+        let resp = batch("/api/rpc/batch", r#"[
+            {"path": "test/device/value", "method": "sleep", "param": 200},
+            {"path": "test/device/value", "method": "sleep", "param": 200}
+        ]"#).await;
+        assert_eq!(resp.status(), Status::Ok);
+        assert!(started.elapsed() >= Duration::from_millis(400));
+    });
+}
+
+#[test]
+fn api_rpc_timeout() {
+    shared_rt_test(async {
+        let config = ProgramConfig {
+            rpc_timeout: Some(Duration::from_secs(1)),
+            ..program_config()
+        };
+        let client = RocketClient::untracked(build_rocket(config)).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let rpc_call = |body: &'static str| client
+            .post("/api/rpc")
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+
+        // The default timeout
+        let resp = rpc_call(r#"{"path": "test/device/value", "method": "sleep", "param": 3000}"#).await;
+        assert_eq!(resp.status(), Status::GatewayTimeout);
+        let body = resp.into_json::<ErrorResponseBody>().await.unwrap();
+        assert!(body.detail.contains("timed out"), "Unexpected detail: {}", body.detail);
+        assert_eq!(body.shv_error, None);
+        let rpc_error = body.rpc_error.unwrap();
+        assert_eq!(rpc_error.name, "GatewayTimeout");
+        assert_eq!(rpc_error.code, None);
+        assert_eq!((rpc_error.path.as_str(), rpc_error.method.as_str()), ("test/device/value", "sleep"));
+
+        // A lower timeout
+        let resp = rpc_call(r#"{"path": "test/device/value", "method": "sleep", "param": 200, "timeout": "50ms"}"#).await;
+        assert_eq!(resp.status(), Status::GatewayTimeout);
+
+        // A higher timeout is capped by the default one
+        let resp = rpc_call(r#"{"path": "test/device/value", "method": "sleep", "param": 1500, "timeout": "1m"}"#).await;
+        assert_eq!(resp.status(), Status::GatewayTimeout);
+
+        let resp = rpc_call(r#"{"path": "test/device/value", "method": "sleep", "param": 100, "timeout": "500ms"}"#).await;
+        assert_eq!(resp.status(), Status::Ok);
+
+        let resp = rpc_call(r#"{"path": "test/device/value", "method": "sleep", "param": 100, "timeout": "soon"}"#).await;
+        assert_eq!(resp.status(), Status::UnprocessableEntity);
+    });
+}
+
+#[test]
+fn api_rpc_cancel() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let rpc_call = |uri: &'static str, body: &'static str| client
+            .post(uri)
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+        let rpc_cancel = |uri: &'static str| client
+            .delete(uri)
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .dispatch();
+
+        let long_call = async {
+            let started = std::time::Instant::now();
+            let resp = rpc_call("/api/rpc?call_id=long", r#"{"path": "test/device/value", "method": "sleep", "param": 5000}"#).await;
+            (resp, started.elapsed())
+        };
+        let cancel = async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let resp = rpc_call("/api/rpc?call_id=long", r#"{"path": "test/device/value", "method": "echo", "param": 1}"#).await;
+            assert_eq!(resp.status(), Status::Conflict);
+            rpc_cancel("/api/rpc/long").await
+        };
+        let ((resp, elapsed), cancel_resp) = rocket::futures::join!(long_call, cancel);
+        assert_eq!(cancel_resp.status(), Status::Ok);
+        assert_eq!(resp.status(), Status::new(499));
+        assert!(elapsed < Duration::from_secs(1), "The call has not been cancelled: {elapsed:?}");
+
+        let resp = rpc_cancel("/api/rpc/long").await;
+        assert_eq!(resp.status(), Status::NotFound);
+
+        // The ID can be reused after the call has finished
+        let resp = rpc_call("/api/rpc?call_id=long", r#"{"path": "test/device/value", "method": "echo", "param": 1}"#).await;
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(resp.into_string().await.unwrap(), "1");
+    });
+}
+
+#[test]
+fn api_rpc_keepalive() {
+    shared_rt_test(async {
+        use tokio::io::AsyncReadExt;
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let rpc_call = |uri: &'static str, body: &'static str| client
+            .post(uri)
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+
+        let resp = rpc_call("/api/rpc?keepalive=true", r#"{"path": "test/device/value", "method": "sleep", "param": 350}"#).await;
+        assert_eq!(resp.status(), Status::Ok);
+        let body = resp.into_string().await.unwrap();
+        assert!(body.starts_with("   "), "Missing keepalive spaces: {body:?}");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap(), serde_json::json!({"result": null}));
+
+        let resp = rpc_call("/api/rpc?keepalive=true", r#"{"path": "test/device/value", "method": "nonexistent"}"#).await;
+        assert_eq!(resp.status(), Status::Ok);
+        let body = resp.into_json::<serde_json::Value>().await.unwrap();
+        assert_eq!(body["error"]["rpc_error"]["name"], "MethodNotFound");
+
+        let resp = client
+            .post("/api/rpc?keepalive=true")
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(ContentType::JSON)
+            .header(rocket::http::Header::new("Accept", "application/x-chainpack"))
+            .body(r#"{"path": "test/device/value", "method": "echo", "param": 1}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::NotAcceptable);
+
+        // A disconnected client drops the call
+        let mut resp = rpc_call("/api/rpc?call_id=long&keepalive=true", r#"{"path": "test/device/value", "method": "sleep", "param": 5000}"#).await;
+        assert_eq!(resp.status(), Status::Ok);
+        let mut space = [0u8];
+        resp.read_exact(&mut space).await.unwrap();
+        assert_eq!(&space, b" ");
+        drop(resp);
+        let resp = client
+            .delete("/api/rpc/long")
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::NotFound);
+    });
+}
+
+#[test]
+fn api_rpc_stream() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let rpc_stream = async |body: &'static str| {
+            let resp = client
+                .post("/api/rpc/stream")
+                .header(rocket::http::Header::new("Authorization", session_id.clone()))
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+                .await;
+            assert_eq!(resp.status(), Status::Ok);
+            sse_codec::decode_stream(tokio::io::BufReader::new(resp).compat())
+                .filter_map(async |event| match event.unwrap_or_else(|e| panic!("Unexpected error in event stream: {e}")) {
+                    sse_codec::Event::Message { event, data, .. } => Some((event, data)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .await
+        };
+
+        let events = rpc_stream(r#"{"path": "test/device/value", "method": "progress", "param": 4}"#).await;
+        assert_eq!(events, [
+            ("progress".to_string(), "0".to_string()),
+            ("progress".to_string(), "0.25".to_string()),
+            ("progress".to_string(), "0.5".to_string()),
+            ("progress".to_string(), "0.75".to_string()),
+            ("result".to_string(), "null".to_string()),
+        ]);
+
+        let events = rpc_stream(r#"{"path": "test/device/value", "method": "echo", "param": 42}"#).await;
+        assert_eq!(events, [("result".to_string(), "42".to_string())]);
+
+        let events = rpc_stream(r#"{"path": "test/device/faulty", "method": "get"}"#).await;
+        let [(event, data)] = events.as_slice() else {
+            panic!("Unexpected events: {events:?}");
+        };
+        assert_eq!(event, "error");
+        let body = serde_json::from_str::<ErrorResponseBody>(data).unwrap();
+        assert_eq!(body.code, Status::InternalServerError.code);
+        assert!(body.detail.contains("Sensor failure"), "Unexpected detail: {}", body.detail);
+
+        // Errors detected before the call has started are reported by the status
+        let resp = client
+            .post("/api/rpc/stream")
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(ContentType::JSON)
+            .body(r#"{"path": "test/device/value"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::UnprocessableEntity);
+    });
+}
+
+#[test]
+fn api_rpc_content_negotiation() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let cpon = ContentType::new("application", "x-cpon");
+        let chainpack = ContentType::new("application", "x-chainpack");
+        let rpc_call = |content_type: ContentType, accept: Option<&'static str>, body: Vec<u8>| {
+            let mut req = client
+                .post("/api/rpc")
+                .header(rocket::http::Header::new("Authorization", session_id.clone()))
+                .header(content_type)
+                .body(body);
+            if let Some(accept) = accept {
+                req.add_header(rocket::http::Header::new("Accept", accept));
+            }
+            req.dispatch()
+        };
+
+        let echo_param = RpcValue::from_cpon(r#"{"u":42u,"d":1.25,"t":d"2024-01-02T03:04:05Z","b":b"\01\02"}"#).unwrap();
+        let request = RpcValue::from(shvproto::make_map!(
+            "path" => "test/device/value",
+            "method" => "echo",
+            "param" => echo_param.clone(),
+        ));
+
+        // CPON request, CPON response
+        let resp = rpc_call(cpon.clone(), Some("application/x-cpon"), request.to_cpon().into_bytes()).await;
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(resp.content_type(), Some(cpon.clone()));
+        assert_eq!(RpcValue::from_cpon(resp.into_string().await.unwrap()).unwrap(), echo_param);
+
+        // ChainPack request, the preferred format of the Accept header
+        let resp = rpc_call(chainpack.clone(), Some("application/json;q=0.5, application/x-chainpack"), request.to_chainpack()).await;
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(resp.content_type(), Some(chainpack.clone()));
+        assert_eq!(RpcValue::from_chainpack(resp.into_bytes().await.unwrap()).unwrap(), echo_param);
+
+        // JSON by default
+        let resp = rpc_call(cpon.clone(), Some("*/*"), request.to_cpon().into_bytes()).await;
+        assert_eq!(resp.content_type(), Some(ContentType::JSON));
+        let resp = rpc_call(chainpack.clone(), None, request.to_chainpack()).await;
+        assert_eq!(resp.content_type(), Some(ContentType::JSON));
+
+        let resp = rpc_call(cpon.clone(), None, b"{\"path\":".to_vec()).await;
+        assert_eq!(resp.status(), Status::UnprocessableEntity);
+        let body = resp.into_json::<ErrorResponseBody>().await.unwrap();
+        assert!(body.detail.starts_with("Cannot parse CPON"), "Unexpected detail: {}", body.detail);
+
+        let resp = rpc_call(ContentType::Plain, None, request.to_cpon().into_bytes()).await;
+        assert_eq!(resp.status(), Status::UnsupportedMediaType);
+
+        // Batch
+        let batch = RpcValue::from(vec![request.clone(), request]);
+        let resp = client
+            .post("/api/rpc/batch")
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(chainpack.clone())
+            .header(rocket::http::Header::new("Accept", "application/x-chainpack"))
+            .body(batch.to_chainpack())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let items = RpcValue::from_chainpack(resp.into_bytes().await.unwrap()).unwrap();
+        let expected_item = RpcValue::from(shvproto::make_map!("result" => echo_param));
+        assert_eq!(items, RpcValue::from(vec![expected_item.clone(), expected_item]));
+    });
+}
+
+#[test]
+fn typed_json_round_trip() {
+    let mut with_meta = RpcValue::from(shvproto::make_imap!(1 => "foo", 2 => 42u64));
+    let mut meta = shvproto::MetaMap::new();
+    meta.insert(1, RpcValue::from(42));
+    meta.insert("name", RpcValue::from(shvproto::Decimal::new(125, -2)));
+    with_meta.meta = Some(Box::new(meta));
+    let values = [
+        RpcValue::null(),
+        RpcValue::from(true),
+        RpcValue::from(-42),
+        RpcValue::from(42u64),
+        RpcValue::from(1.0),
+        RpcValue::from(f64::INFINITY),
+        RpcValue::from(shvproto::Decimal::new(125, -2)),
+        RpcValue::from_cpon(r#"d"2024-01-02T03:04:05.678+0130""#).unwrap(),
+        RpcValue::from("text"),
+        RpcValue::from(&b"\x00\x01\xff"[..]),
+        RpcValue::from(shvproto::make_map!("$type" => "Int", "value" => 42u64)),
+        RpcValue::from(vec![RpcValue::from(1), RpcValue::from(1u64), with_meta]),
+    ];
+    for value in values {
+        let json = crate::to_typed_json(&value).unwrap();
+        let decoded = crate::from_typed_json(&serde_json::from_str(&json.to_string()).unwrap()).unwrap();
+        assert_eq!(decoded, value, "typed JSON: {json}");
+        assert_eq!(decoded.type_name(), value.type_name());
+    }
+
+    assert_eq!(crate::to_typed_json(&RpcValue::from(42u64)).unwrap(), serde_json::json!({"$type": "UInt", "value": 42}));
+    assert_eq!(
+        crate::from_typed_json(&serde_json::json!({"a": [1, 2.5, {"$type": "Blob", "value": "AQI="}]})).unwrap(),
+        RpcValue::from(shvproto::make_map!("a" => shvproto::make_list!(1, 2.5, &b"\x01\x02"[..])))
+    );
+    assert!(crate::from_typed_json(&serde_json::json!({"$type": "UInt", "value": -1})).is_err());
+    assert!(crate::from_typed_json(&serde_json::json!({"$type": "Foo", "value": 1})).is_err());
+    assert!(crate::from_typed_json(&serde_json::json!({"$meta": {}, "value": 1})).is_err());
+}
+
+#[test]
+fn rpc_error_names() {
+    use shvrpc::rpcmessage::{RpcErrorCodeKind, USER_ERROR_CODE_DEFAULT};
+    let rpc_errors = (0..=USER_ERROR_CODE_DEFAULT as i32)
+        .map(|code| CallRpcMethodErrorKind::RpcError(RpcError {
+            code: RpcErrorCodeKind::try_from(code).unwrap(),
+            message: String::new(),
+        }));
+    let names = [
+        CallRpcMethodErrorKind::ConnectionClosed,
+        CallRpcMethodErrorKind::InvalidMessage(String::new()),
+        CallRpcMethodErrorKind::ResultTypeMismatch(String::new()),
+    ]
+        .into_iter()
+        .chain(rpc_errors)
+        .map(|error| crate::rpc_error_name(&error))
+        .collect::<std::collections::BTreeSet<_>>();
+    let expected_names = crate::RPC_ERROR_NAMES.iter().map(|name| name.to_string()).collect::<std::collections::BTreeSet<_>>();
+    assert_eq!(expected_names.len(), crate::RPC_ERROR_NAMES.len(), "Duplicate error names");
+    assert_eq!(names, expected_names);
+}
+
+#[test]
+fn meta_entries() {
+    let mut meta = shvproto::MetaMap::new();
+    meta.insert(1, RpcValue::from(42));
+    meta.insert("1", RpcValue::from("numeric-looking"));
+    meta.insert("name", RpcValue::from(shvproto::make_map!("nested" => 1u64)));
+    assert_eq!(crate::meta_entries(&meta).unwrap(), [
+        (RpcValue::from(1), RpcValue::from(42)),
+        (RpcValue::from("1"), RpcValue::from("numeric-looking")),
+        (RpcValue::from("name"), RpcValue::from(shvproto::make_map!("nested" => 1u64))),
+    ]);
+    assert_eq!(crate::meta_entries(&shvproto::MetaMap::new()).unwrap(), []);
+}
+
+#[test]
+fn api_rpc_typed_json() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let typed_json = ContentType::new("application", "x-typed-json");
+
+        let param = serde_json::json!({
+            "u": {"$type": "UInt", "value": 42},
+            "d": {"$type": "Decimal", "value": {"mantissa": 125, "exponent": -2}},
+            "t": {"$type": "DateTime", "value": "2024-01-02T03:04:05.000Z"},
+        });
+        let resp = client
+            .post("/api/rpc")
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(typed_json.clone())
+            .header(rocket::http::Header::new("Accept", "application/x-typed-json"))
+            .body(serde_json::json!({"path": "test/device/value", "method": "echo", "param": param}).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(resp.content_type(), Some(typed_json.clone()));
+        assert_eq!(resp.into_json::<serde_json::Value>().await.unwrap(), param);
+
+        let resp = client
+            .post("/api/rpc")
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(typed_json)
+            .body(r#"{"path": "test/device/value", "method": "echo", "param": {"$type": "UInt", "value": "42"}}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::UnprocessableEntity);
+        let body = resp.into_json::<ErrorResponseBody>().await.unwrap();
+        assert!(body.detail.starts_with("Cannot parse typed JSON"), "Unexpected detail: {}", body.detail);
+
+        let resp = client
+            .get(format!("/api/subscribe?session_id={session_id}&shv_ri=test/device/value:*:event&typed_json=true"))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let mut reader = sse_codec::decode_stream(tokio::io::BufReader::new(resp).compat());
+        let event = reader
+            .next()
+            .await
+            .expect("Unexpected end of stream")
+            .unwrap_or_else(|e| panic!("Unexpected error in event stream: {e}"));
+        let sse_codec::Event::Message{ data, .. } = event else {
+            panic!("Unexpected event");
+        };
+        let parsed_data: SubscribeEvent = crate::from_typed_json(&serde_json::from_str(&data).unwrap()).unwrap().try_into().unwrap();
+        assert_eq!(parsed_data.path, Some("test/device/value".into()));
+        assert_eq!(parsed_data.param, Some(RpcValue::from(42)));
+    });
+}
+
+#[test]
+fn coerce_param() {
+    use crate::ParamType;
+    assert_eq!(crate::parse_param_types("UInt"), Some(vec![ParamType::UInt]));
+    assert_eq!(crate::parse_param_types("i(0,100)|n"), Some(vec![ParamType::Int, ParamType::Null]));
+    assert_eq!(crate::parse_param_types(""), None);
+    assert_eq!(crate::parse_param_types("[Int]"), None);
+
+    assert_eq!(crate::parse_decimal("1.25"), Some(shvproto::Decimal::new(125, -2)));
+    assert_eq!(crate::parse_decimal("-1.5e3"), Some(shvproto::Decimal::new(-15, 2)));
+    assert_eq!(crate::parse_decimal("100"), Some(shvproto::Decimal::new(1, 2)));
+    assert_eq!(crate::parse_decimal("0.1x"), None);
+    assert_eq!(crate::parse_decimal("123456789012345678.9"), None);
+
+    let coerce = |param: RpcValue, signature: &str| crate::coerce_param(param, &crate::parse_param_types(signature).unwrap());
+    assert_eq!(coerce(RpcValue::from(42), "UInt"), Ok(RpcValue::from(42u64)));
+    assert_eq!(coerce(RpcValue::from(42), "Int"), Ok(RpcValue::from(42)));
+    assert_eq!(coerce(RpcValue::from(0.1), "Decimal"), Ok(RpcValue::from(shvproto::Decimal::new(1, -1))));
+    assert_eq!(coerce(RpcValue::from(3), "Double"), Ok(RpcValue::from(3.0)));
+    assert_eq!(coerce(RpcValue::from(3.0), "u"), Ok(RpcValue::from(3u64)));
+    assert_eq!(coerce(RpcValue::from("2024-01-02T03:04:05Z"), "DateTime"), Ok(RpcValue::from_cpon(r#"d"2024-01-02T03:04:05Z""#).unwrap()));
+    assert_eq!(coerce(RpcValue::null(), "Decimal|Null"), Ok(RpcValue::null()));
+    assert_eq!(coerce(RpcValue::from(-1), "UInt"), Err("cannot convert Int `-1` to UInt: out of range".into()));
+    assert_eq!(coerce(RpcValue::from(1.5), "Int"), Err("cannot convert Double `1.5` to Int: not an integer".into()));
+    assert!(coerce(RpcValue::from("foo"), "Int|Null").is_err());
+}
+
+#[test]
+fn api_rpc_coerce_params() {
+    shared_rt_test(async {
+        for coerce_params in [false, true] {
+            let config = ProgramConfig {
+                coerce_params,
+                ..program_config()
+            };
+            let client = RocketClient::untracked(build_rocket(config)).await.unwrap();
+
+            let resp = client
+                .post("/api/login")
+                .header(ContentType::JSON)
+                .body(r#"{"username": "admin", "password": "admin"}"#)
+                .dispatch()
+                .await;
+            let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+            let rpc_call = |body: &'static str| client
+                .post("/api/rpc")
+                .header(rocket::http::Header::new("Authorization", session_id.clone()))
+                .header(ContentType::JSON)
+                .header(rocket::http::Header::new("Accept", "application/x-cpon"))
+                .body(body)
+                .dispatch();
+
+            let resp = rpc_call(r#"{"path": "test/device/value", "method": "echoUInt", "param": 42}"#).await;
+            assert_eq!(resp.status(), Status::Ok);
+            let expected = if coerce_params { RpcValue::from(42u64) } else { RpcValue::from(42) };
+            assert_eq!(RpcValue::from_cpon(resp.into_string().await.unwrap()).unwrap(), expected);
+
+            let resp = rpc_call(r#"{"path": "test/device/value", "method": "echoDecimal", "param": 2}"#).await;
+            assert_eq!(resp.status(), Status::Ok);
+            let result = RpcValue::from_cpon(resp.into_string().await.unwrap()).unwrap();
+            assert_eq!(result.is_decimal(), coerce_params, "Unexpected result: {result}");
+
+            let resp = rpc_call(r#"{"path": "test/device/value", "method": "echoUInt", "param": -1}"#).await;
+            if coerce_params {
+                assert_eq!(resp.status(), Status::UnprocessableEntity);
+                let body = resp.into_json::<ErrorResponseBody>().await.unwrap();
+                assert!(body.detail.contains("cannot convert Int `-1` to UInt"), "Unexpected detail: {}", body.detail);
+            } else {
+                assert_eq!(resp.status(), Status::Ok);
+            }
+
+            // Methods without a known param type are called as they are
+            let resp = rpc_call(r#"{"path": "test/device/value", "method": "echo", "param": 42}"#).await;
+            assert_eq!(resp.status(), Status::Ok);
+            assert_eq!(RpcValue::from_cpon(resp.into_string().await.unwrap()).unwrap(), RpcValue::from(42));
+        }
+    });
+}
+
+#[test]
+fn api_value() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let auth_header = || rocket::http::Header::new("Authorization", session_id.clone());
+
+        let resp = client
+            .put("/api/value/test/device/setpoint%201")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body("42")
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::NoContent);
+
+        let resp = client
+            .get("/api/value/test/device/setpoint%201")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(resp.into_string().await.unwrap(), "42");
+
+        let resp = client
+            .get("/api/value/test/device/setpoint%201")
+            .header(auth_header())
+            .header(rocket::http::Header::new("Accept", "application/x-cpon"))
+            .dispatch()
+            .await;
+        assert_eq!(resp.into_string().await.unwrap(), "42");
+
+        let resp = client
+            .put("/api/value/test/device/setpoint%201")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#""foo""#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::BadRequest);
+        let rpc_error = resp.into_json::<ErrorResponseBody>().await.unwrap().rpc_error.unwrap();
+        assert_eq!(rpc_error.name, "InvalidParam");
+        assert_eq!(rpc_error.path, "test/device/setpoint 1");
+        assert_eq!(rpc_error.method, "set");
+
+        let resp = client
+            .get("/api/value/test/device/nonexistent")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::NotFound);
+
+        let resp = client
+            .get("/api/value/test/device/setpoint%201")
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::BadRequest);
+    });
+}
+
+#[test]
+fn api_ls_dir() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let auth_header = || rocket::http::Header::new("Authorization", session_id.clone());
+
+        let resp = client
+            .get("/api/ls/test/device")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let nodes = resp.into_json::<serde_json::Value>().await.unwrap();
+        let nodes = nodes.as_array().unwrap();
+        assert!(nodes.contains(&serde_json::json!({"name": "value"})));
+        assert!(nodes.contains(&serde_json::json!({"name": "setpoint 1"})));
+
+        let resp = client
+            .get("/api/ls/")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let nodes = resp.into_json::<serde_json::Value>().await.unwrap();
+        assert!(nodes.as_array().unwrap().contains(&serde_json::json!({"name": "test"})));
+
+        let resp = client
+            .get("/api/ls/test?depth=2")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let nodes = resp.into_json::<serde_json::Value>().await.unwrap();
+        let device = nodes.as_array().unwrap().iter().find(|node| node["name"] == "device").unwrap();
+        assert!(device["nodes"].as_array().unwrap().contains(&serde_json::json!({"name": "setpoint 1"})));
+
+        let resp = client
+            .get("/api/dir/test/device/setpoint%201")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let node = resp.into_json::<serde_json::Value>().await.unwrap();
+        assert!(node.get("nodes").is_none());
+        let methods = node["methods"].as_array().unwrap();
+        assert!(methods.contains(&serde_json::json!({
+            "name": "get", "flags": ["IsGetter"], "access": "rd", "param": "", "result": "Int", "signals": {}
+        })));
+        assert!(methods.contains(&serde_json::json!({
+            "name": "set", "flags": ["IsSetter"], "access": "wr", "param": "Int", "result": "", "signals": {}
+        })));
+
+        let resp = client
+            .get("/api/dir/test/device?depth=2")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let node = resp.into_json::<serde_json::Value>().await.unwrap();
+        assert!(node["methods"].as_array().unwrap().iter().any(|method| method["name"] == "ls"));
+        let value = node["nodes"].as_array().unwrap().iter().find(|node| node["name"] == "value").unwrap();
+        assert!(value["methods"].as_array().unwrap().iter().any(|method| method["name"] == "echoDecimal"));
+        assert!(value.get("nodes").is_none());
+
+        let resp = client
+            .get("/api/dir/test/device?depth=0")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        let resp = client
+            .get("/api/ls/test/device/nonexistent")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::NotFound);
+    });
+}
+
+#[test]
+fn api_tree() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let auth_header = || rocket::http::Header::new("Authorization", session_id.clone());
+
+        let resp = client
+            .get("/api/tree/test")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let tree = resp.into_json::<serde_json::Value>().await.unwrap();
+        assert_eq!(tree["path"], "test");
+        let device = tree["nodes"].as_array().unwrap().iter().find(|node| node["name"] == "device").unwrap();
+        let setpoint = device["nodes"].as_array().unwrap().iter().find(|node| node["name"] == "setpoint 1").unwrap();
+        assert!(setpoint["methods"].as_array().unwrap().contains(&serde_json::json!({
+            "name": "get", "flags": ["IsGetter"], "access": "rd", "param": "", "result": "Int", "signals": {}
+        })));
+        assert_eq!(setpoint["nodes"], serde_json::json!([]));
+
+        fn count_nodes(node: &serde_json::Value) -> u64 {
+            1 + node.get("nodes").map_or(0, |nodes| nodes.as_array().unwrap().iter().map(count_nodes).sum())
+        }
+        assert_eq!(tree["node_count"].as_u64().unwrap(), count_nodes(&tree));
+
+        let resp = client
+            .get("/api/tree/test/device?depth=1")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let tree = resp.into_json::<serde_json::Value>().await.unwrap();
+        assert_eq!(tree["node_count"], 1);
+        assert!(tree.get("nodes").is_none());
+
+        let resp = client
+            .get("/api/tree/test?max_nodes=2")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::UnprocessableEntity);
+
+        let resp = client
+            .get("/api/tree/test?max_nodes=1000000")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        let resp = client
+            .get("/api/tree/test?depth=100")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // An unreadable subtree is recorded on its node
+        let policy = Policy::from_json(r#"{"deny": ["test/device/value:ls"]}"#).unwrap();
+        let client = RocketClient::untracked(build_rocket(ProgramConfig { policy: Some(policy), ..program_config() })).await.unwrap();
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let resp = client
+            .get("/api/tree/test/device")
+            .header(rocket::http::Header::new("Authorization", session_id))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let tree = resp.into_json::<serde_json::Value>().await.unwrap();
+        let value = tree["nodes"].as_array().unwrap().iter().find(|node| node["name"] == "value").unwrap();
+        assert_eq!(value["error"]["method"], "ls");
+        assert_eq!(value["error"]["code"], 403);
+        assert!(value.get("nodes").is_none());
+        let setpoint = tree["nodes"].as_array().unwrap().iter().find(|node| node["name"] == "setpoint 1").unwrap();
+        assert!(setpoint.get("error").is_none());
+        assert_eq!(setpoint["nodes"], serde_json::json!([]));
+    });
+}
+
+#[test]
+fn api_values() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let auth_header = || rocket::http::Header::new("Authorization", session_id.clone());
+
+        let resp = client
+            .get("/api/values/test/device")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let body = resp.into_json::<serde_json::Value>().await.unwrap();
+        assert_eq!(body["errors"], serde_json::json!({}));
+        let values = &body["values"];
+        assert!(values["test/device/setpoint 1:get"]["result"].is_i64());
+        assert_eq!(values["test/device/value:echo"], serde_json::json!({"result": null}));
+        assert!(values.get("test/device/value:sleep").is_none());
+        assert!(values.get("test/device/setpoint 1:set").is_none());
+        let error = &values["test/device/faulty:get"]["error"];
+        assert_eq!(error["rpc_error"]["name"], "MethodCallException");
+        assert_eq!(error["rpc_error"]["message"], "Sensor failure");
+
+        let resp = client
+            .get("/api/values/test/device/setpoint%201")
+            .header(auth_header())
+            .header(rocket::http::Header::new("Accept", "application/x-cpon"))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let values = RpcValue::from_cpon(resp.into_string().await.unwrap()).unwrap();
+        assert_eq!(values.as_map()["values"].as_map().keys().collect::<Vec<_>>(), ["test/device/setpoint 1:get"]);
+
+        let resp = client
+            .get("/api/values/test/device?max_nodes=2")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::UnprocessableEntity);
+
+        // A node failing the discovery does not fail the other nodes
+        let policy = Policy::from_json(r#"{"deny": ["test/device/value:dir"]}"#).unwrap();
+        let client = RocketClient::untracked(build_rocket(ProgramConfig { policy: Some(policy), ..program_config() })).await.unwrap();
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let resp = client
+            .get("/api/values/test/device")
+            .header(rocket::http::Header::new("Authorization", session_id))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let body = resp.into_json::<serde_json::Value>().await.unwrap();
+        assert!(body["values"]["test/device/setpoint 1:get"]["result"].is_i64());
+        assert!(body["values"].get("test/device/value:echo").is_none());
+        assert!(body["values"].get("test/device/value:dir").is_none());
+        assert_eq!(body["errors"]["test/device/value"]["method"], "dir");
+        assert_eq!(body["errors"]["test/device/value"]["code"], 403);
+    });
+}
+
+#[test]
+fn api_rpc_apply() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let login = async |credentials: &'static str| {
+            let resp = client
+                .post("/api/login")
+                .header(ContentType::JSON)
+                .body(credentials)
+                .dispatch()
+                .await;
+            resp.into_json::<LoginResponse>().await.unwrap().session_id
+        };
+        let admin_session_id = login(r#"{"username": "admin", "password": "admin"}"#).await;
+        let user_session_id = login(r#"{"username": "user", "password": "user"}"#).await;
+
+        let apply = async |session_id: &str, query: &str, body: &'static str| {
+            let resp = client
+                .post(format!("/api/rpc/apply{query}"))
+                .header(rocket::http::Header::new("Authorization", session_id.to_string()))
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+                .await;
+            assert_eq!(resp.status(), Status::Ok);
+            resp.into_json::<serde_json::Value>().await.unwrap()
+        };
+
+        let writes = r#"[
+            {"path": "test/device/value", "method": "sleep", "param": 1},
+            {"path": "test/device/value", "method": "sleep", "param": "foo"},
+            {"path": "test/device/value", "method": "echo", "param": 3}
+        ]"#;
+        let items = apply(&admin_session_id, "", writes).await;
+        assert_eq!(items[0], serde_json::json!({"result": null}));
+        assert_eq!(items[1]["error"]["rpc_error"]["name"], "InvalidParam");
+        assert_eq!(items[2], serde_json::json!({"result": 3}));
+
+        let items = apply(&admin_session_id, "?stop_on_error=true", writes).await;
+        assert_eq!(items[0], serde_json::json!({"result": null}));
+        assert_eq!(items[1]["error"]["rpc_error"]["name"], "InvalidParam");
+        assert_eq!(items[2]["error"]["code"], 424);
+
+        let items = apply(&admin_session_id, "?dry_run=true", r#"[
+            {"path": "test/device/value", "method": "sleep", "param": 1},
+            {"path": "test/device/value", "method": "nonexistent", "param": 1},
+            {"path": "test/device/nonexistent", "method": "set", "param": 1}
+        ]"#).await;
+        assert_eq!(items[0], serde_json::json!({"result": null}));
+        assert_eq!(items[1]["error"]["code"], 404);
+        assert_eq!(items[1]["error"]["detail"], "Method `nonexistent` does not exist on path `test/device/value`");
+        assert_eq!(items[2]["error"]["code"], 404);
+
+        let items = apply(&user_session_id, "?dry_run=true", r#"[
+            {"path": "test/device/value", "method": "sleep", "param": 1}
+        ]"#).await;
+        assert_eq!(items[0]["error"]["code"], 403);
+        assert_eq!(
+            items[0]["error"]["detail"],
+            "Method `sleep` on path `test/device/value` requires the `wr` access level, which is not granted to the user"
+        );
+    });
+}
+
+#[test]
+fn policy() {
+    let shv_ri = |ri: &str| ShvRI::try_from(ri).unwrap();
+    let policy = Policy::from_json(r#"{"allow": ["test/**:*", ".app:ping"], "deny": ["test/secret/**:*", "**:set"]}"#).unwrap();
+    assert!(policy.check(&shv_ri("test/device/value:get")).is_ok());
+    assert!(policy.check(&shv_ri("test/device/value:get:chng")).is_ok());
+    assert!(policy.check(&shv_ri(".app:ping")).is_ok());
+    assert_eq!(
+        policy.check(&shv_ri(".app:name")),
+        Err("`.app:name` is not allowed by any gateway policy rule".into())
+    );
+    assert_eq!(
+        policy.check(&shv_ri("test/device/value:set")),
+        Err("`test/device/value:set` is denied by the gateway policy rule `**:set`".into())
+    );
+    assert!(policy.check(&shv_ri("test/secret/key:get:chng")).is_err());
+
+    let policy = Policy::from_json(r#"{"deny": ["test/device/value:*:event"]}"#).unwrap();
+    assert!(policy.check(&shv_ri("foo/bar:get")).is_ok());
+    assert!(policy.check(&shv_ri("test/device/value:get:chng")).is_ok());
+    assert!(policy.check(&shv_ri("test/device/value:get:event")).is_err());
+
+    // A call is matched against the `<path>:<method>` part of a rule, whatever its signal is
+    let policy = Policy::from_json(r#"{"deny": ["test/**:*:*", "other/**:get:chng"]}"#).unwrap();
+    assert_eq!(
+        policy.check(&shv_ri("test/device/value:get")),
+        Err("`test/device/value:get` is denied by the gateway policy rule `test/**:*:*`".into())
+    );
+    assert!(policy.check(&shv_ri("test/device/value:get:chng")).is_err());
+    assert!(policy.check(&shv_ri("other/device:get")).is_err());
+    assert!(policy.check(&shv_ri("other/device:get:fchng")).is_ok());
+    assert!(policy.check(&shv_ri("other/device:set")).is_ok());
+    assert!(policy.check(&shv_ri("foo/bar:get")).is_ok());
+
+    assert!(Policy::from_json(r#"{"deny": ["missing-method"]}"#).is_err());
+    assert!(Policy::from_json(r#"{"allow": "**:*"}"#).is_err());
+}
+
+#[test]
+fn api_policy() {
+    shared_rt_test(async {
+        let policy = Policy::from_json(r#"{
+            "allow": ["test/**:*"],
+            "deny": ["test/device/setpoint 1:set", "test/device/value:*:event"]
+        }"#).unwrap();
+        let client = RocketClient::untracked(build_rocket(ProgramConfig { policy: Some(policy), ..program_config() })).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let auth_header = || rocket::http::Header::new("Authorization", session_id.clone());
+
+        let resp = client
+            .post("/api/rpc")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#"{"path": "test/device/setpoint 1", "method": "get"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+
+        // The signal part of a rule does not matter for calls
+        let resp = client
+            .post("/api/rpc")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#"{"path": "test/device/value", "method": "echo", "param": 1}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Forbidden);
+
+        let resp = client
+            .post("/api/rpc")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#"{"path": ".app", "method": "name"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Forbidden);
+        assert_eq!(resp.into_json::<ErrorResponseBody>().await.unwrap().detail, "`.app:name` is not allowed by any gateway policy rule");
+
+        let resp = client
+            .put("/api/value/test/device/setpoint%201")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body("1")
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Forbidden);
+        assert_eq!(
+            resp.into_json::<ErrorResponseBody>().await.unwrap().detail,
+            "`test/device/setpoint 1:set` is denied by the gateway policy rule `test/device/setpoint 1:set`"
+        );
+
+        let resp = client
+            .get("/api/dir/")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Forbidden);
+
+        let resp = client
+            .post("/api/subscribe")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#"{"shv_ri": "test/device/value:*:event"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Forbidden);
+
+        let resp = client
+            .post("/api/subscriptions")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#"{"shv_ri": "other/**:*:*"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Forbidden);
+
+        // The notifications of a wildcard subscription are filtered
+        let resp = client
+            .post("/api/subscribe")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#"{"shv_ri": "test/**:*:*"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let mut reader = sse_codec::decode_stream(tokio::io::BufReader::new(resp).compat());
+        assert!(tokio::time::timeout(Duration::from_millis(500), reader.next()).await.is_err());
+
+        // The `dir` and `accessLevelForMethodCall` calls made by the gateway itself are not checked
+        let policy = Policy::from_json(r#"{"allow": ["test/**:echo"]}"#).unwrap();
+        let client = RocketClient::untracked(build_rocket(ProgramConfig {
+            policy: Some(policy),
+            read_only: true,
+            coerce_params: true,
+            ..program_config()
+        })).await.unwrap();
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let auth_header = || rocket::http::Header::new("Authorization", session_id.clone());
+
+        let resp = client
+            .post("/api/rpc")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#"{"path": "test/device/value", "method": "echo", "param": 1}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+
+        let resp = client
+            .post("/api/rpc/apply?dry_run=true")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#"[{"path": "test/device/value", "method": "echo", "param": 1}]"#)
+            .dispatch()
+            .await;
+        let items = resp.into_json::<serde_json::Value>().await.unwrap();
+        assert_eq!(items[0], serde_json::json!({"result": null}));
+    });
+}
+
+#[test]
+fn api_read_only() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(ProgramConfig { read_only: true, ..program_config() })).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let auth_header = || rocket::http::Header::new("Authorization", session_id.clone());
+
+        let call = async |body: &'static str| {
+            client
+                .post("/api/rpc")
+                .header(auth_header())
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+                .await
+        };
+
+        let resp = call(r#"{"path": "test/device/value", "method": "echo", "param": 1}"#).await;
+        assert_eq!(resp.status(), Status::Ok);
+
+        let resp = call(r#"{"path": "test/device/value", "method": "ls"}"#).await;
+        assert_eq!(resp.status(), Status::Ok);
+
+        let resp = call(r#"{"path": "test/device/value", "method": "sleep", "param": 1}"#).await;
+        assert_eq!(resp.status(), Status::Forbidden);
+        assert_eq!(
+            resp.into_json::<ErrorResponseBody>().await.unwrap().detail,
+            "The gateway is read-only and method `sleep` on path `test/device/value` requires the `wr` access level"
+        );
+
+        let resp = call(r#"{"path": "test/device/value", "method": "unknown"}"#).await;
+        assert_eq!(resp.status(), Status::Forbidden);
+        assert_eq!(
+            resp.into_json::<ErrorResponseBody>().await.unwrap().detail,
+            "The gateway is read-only and method `unknown` is not listed by `dir` on path `test/device/value`"
+        );
+
+        let resp = call(r#"{"path": "test/device/nonexistent", "method": "get"}"#).await;
+        assert_eq!(resp.status(), Status::NotFound);
+
+        let resp = client
+            .put("/api/value/test/device/setpoint%201")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body("1")
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Forbidden);
+
+        let resp = client
+            .get("/api/value/test/device/setpoint%201")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+
+        let resp = client
+            .post("/api/subscribe")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#"{"shv_ri": "test/device/value:*:*"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+    });
+}
+
+#[test]
+fn parse_byte_range() {
+    use crate::parse_byte_range;
+    assert_eq!(parse_byte_range("bytes=0-99", 1000).unwrap(), Some(0..100));
+    assert_eq!(parse_byte_range("bytes=900-", 1000).unwrap(), Some(900..1000));
+    assert_eq!(parse_byte_range("bytes=-100", 1000).unwrap(), Some(900..1000));
+    assert_eq!(parse_byte_range("bytes=-2000", 1000).unwrap(), Some(0..1000));
+    assert_eq!(parse_byte_range("bytes=990-2000", 1000).unwrap(), Some(990..1000));
+    assert_eq!(parse_byte_range("bytes=0-9, 20-29", 1000).unwrap(), None);
+    assert_eq!(parse_byte_range("bytes=9-0", 1000).unwrap(), None);
+    assert_eq!(parse_byte_range("lines=0-9", 1000).unwrap(), None);
+    assert_eq!(parse_byte_range("bytes=-", 1000).unwrap(), None);
+    assert_eq!(parse_byte_range("bytes=1000-", 1000).unwrap_err().0, Status::RangeNotSatisfiable);
+    assert_eq!(parse_byte_range("bytes=-0", 1000).unwrap_err().0, Status::RangeNotSatisfiable);
+}
+
+#[test]
+fn api_file_get() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(ProgramConfig { file_chunk_size: 300, ..program_config() })).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let auth_header = || rocket::http::Header::new("Authorization", session_id.clone());
+
+        let resp = client
+            .get("/api/file/test/device/file")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(resp.content_type(), Some(ContentType::Binary));
+        assert_eq!(resp.headers().get_one("Content-Length"), Some("1000"));
+        assert_eq!(resp.headers().get_one("Accept-Ranges"), Some("bytes"));
+        assert_eq!(resp.into_bytes().await.unwrap(), *FILE_CONTENT);
+
+        let resp = client
+            .get("/api/file/test/device/file")
+            .header(auth_header())
+            .header(rocket::http::Header::new("Range", "bytes=250-649"))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::PartialContent);
+        assert_eq!(resp.headers().get_one("Content-Length"), Some("400"));
+        assert_eq!(resp.headers().get_one("Content-Range"), Some("bytes 250-649/1000"));
+        assert_eq!(resp.into_bytes().await.unwrap(), FILE_CONTENT[250..650]);
+
+        let resp = client
+            .get("/api/file/test/device/file")
+            .header(auth_header())
+            .header(rocket::http::Header::new("Range", "bytes=1000-"))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::RangeNotSatisfiable);
+
+        let resp = client
+            .get("/api/file/test/device/value")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::NotFound);
+    });
+}
+
+#[test]
+fn api_file_put() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(ProgramConfig {
+            file_chunk_size: 300,
+            max_file_size: rocket::data::ByteUnit::Byte(1000),
+            ..program_config()
+        })).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let auth_header = || rocket::http::Header::new("Authorization", session_id.clone());
+
+        let content = (0..950).map(|i| (i % 253) as u8).collect::<Vec<_>>();
+        let resp = client
+            .put("/api/file/test/device/upload?verify=crc")
+            .header(auth_header())
+            .header(ContentType::Binary)
+            .body(&content)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let report = resp.into_json::<FileUploadReport>().await.unwrap();
+        assert_eq!(report.written, 950);
+        assert_eq!(report.crc, Some(crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(&content)));
+        assert_eq!(*UPLOADED_CONTENT.lock().unwrap(), content);
+
+        let boundary = "X-BOUNDARY";
+        let mut body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"patch.bin\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+        ).into_bytes();
+        body.extend_from_slice(b"patched");
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+        let resp = client
+            .put("/api/file/test/device/upload?offset=100&verify=sha1")
+            .header(auth_header())
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", boundary)))
+            .body(body)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let report = resp.into_json::<FileUploadReport>().await.unwrap();
+        assert_eq!(report.written, 7);
+        assert_eq!(report.sha1.unwrap().len(), 40);
+        assert_eq!(&UPLOADED_CONTENT.lock().unwrap()[100..107], b"patched");
+
+        let resp = client
+            .put("/api/file/test/device/upload")
+            .header(auth_header())
+            .header(ContentType::Binary)
+            .body(vec![0; 1001])
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::PayloadTooLarge);
+
+        let resp = client
+            .put("/api/file/test/device/lossy%20upload?verify=crc")
+            .header(auth_header())
+            .header(ContentType::Binary)
+            .body(&content)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::BadGateway);
+        let detail = resp.into_json::<ErrorResponseBody>().await.unwrap().detail;
+        assert!(detail.starts_with("CRC of the written 950 bytes does not match"), "{detail}");
+    });
+}
+
+#[test]
+fn api_jobs() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(ProgramConfig {
+            max_session_jobs: 3,
+            job_retention: Duration::from_secs(1),
+            rpc_timeout: Some(Duration::from_millis(100)),
+            ..program_config()
+        })).await.unwrap();
+
+        let resp = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username": "admin", "password": "admin"}"#)
+            .dispatch()
+            .await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let auth_header = || rocket::http::Header::new("Authorization", session_id.clone());
+
+        let start_job = async |body: &'static str| {
+            let resp = client
+                .post("/api/jobs")
+                .header(auth_header())
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+                .await;
+            assert_eq!(resp.status(), Status::Ok);
+            let job = resp.into_json::<serde_json::Value>().await.unwrap();
+            assert_eq!(job["status"], "running");
+            job["job_id"].as_u64().unwrap()
+        };
+        let get_job = async |job_id: u64| {
+            let resp = client
+                .get(format!("/api/jobs/{job_id}"))
+                .header(auth_header())
+                .dispatch()
+                .await;
+            assert_eq!(resp.status(), Status::Ok);
+            resp.into_json::<serde_json::Value>().await.unwrap()
+        };
+
+        // The job outlives the default RPC timeout
+        let sleep_job_id = start_job(r#"{"path": "test/device/value", "method": "sleep", "param": 300}"#).await;
+        let failing_job_id = start_job(r#"{"path": "test/device/value", "method": "sleep", "param": "foo"}"#).await;
+        let cancelled_job_id = start_job(r#"{"path": "test/device/value", "method": "sleep", "param": 5000}"#).await;
+
+        let resp = client
+            .post("/api/jobs")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#"{"path": "test/device/value", "method": "echo", "param": 1}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::TooManyRequests);
+
+        let job = get_job(sleep_job_id).await;
+        assert_eq!(job["status"], "running");
+        assert_eq!(job["path"], "test/device/value");
+        assert_eq!(job["method"], "sleep");
+        assert!(job.get("result").is_none());
+
+        let resp = client
+            .delete(format!("/api/jobs/{cancelled_job_id}"))
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let resp = client
+            .get(format!("/api/jobs/{cancelled_job_id}"))
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::NotFound);
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let job = get_job(sleep_job_id).await;
+        assert_eq!(job["status"], "succeeded");
+        assert_eq!(job["result"], serde_json::Value::Null);
+
+        let job = get_job(failing_job_id).await;
+        assert_eq!(job["status"], "failed");
+        assert_eq!(job["error"]["rpc_error"]["name"], "InvalidParam");
+
+        let echo_job_id = start_job(r#"{"path": "test/device/value", "method": "echo", "param": 42}"#).await;
+        let resp = client
+            .get("/api/jobs")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        let jobs = resp.into_json::<serde_json::Value>().await.unwrap();
+        let job_ids = jobs.as_array().unwrap().iter().map(|job| job["job_id"].as_u64().unwrap()).collect::<Vec<_>>();
+        assert_eq!(job_ids, [sleep_job_id, failing_job_id, echo_job_id]);
+
+        // Only the running jobs count toward the limit
+        for _ in 0..2 {
+            start_job(r#"{"path": "test/device/value", "method": "sleep", "param": 100}"#).await;
+        }
+
+        // The finished jobs expire
+        tokio::time::sleep(Duration::from_millis(1200)).await;
+        let resp = client
+            .get("/api/jobs")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.into_json::<serde_json::Value>().await.unwrap(), serde_json::json!([]));
+    });
+}
+
+#[test]
+fn api_device() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let login = async |body: &str| client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await;
+        let resp = login(r#"{"username": "admin", "password": "admin", "device": {"mount": "test/hmi", "methods": [
+            {"name": "confirm", "access": "wr", "param": "String", "result": "Bool"}
+        ]}}"#).await;
+        assert_eq!(resp.status(), Status::Ok);
+        let device_session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let resp = login(r#"{"username": "admin", "password": "admin"}"#).await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let auth_header = || rocket::http::Header::new("Authorization", session_id.clone());
+        let device_auth_header = || rocket::http::Header::new("Authorization", device_session_id.clone());
+
+        let resp = login(r#"{"username": "admin", "password": "admin", "device": {"methods": []}}"#).await;
+        assert_eq!(resp.status(), Status::UnprocessableEntity);
+        let resp = login(r#"{"username": "admin", "password": "admin", "device": {"mount": "test/hmi2", "methods": [{"name": "confirm", "access": "write"}]}}"#).await;
+        assert_eq!(resp.status(), Status::UnprocessableEntity);
+        assert_eq!(resp.into_json::<ErrorResponseBody>().await.unwrap().detail, "Invalid access level `write` of method `confirm`");
+
+        let resp = client
+            .get("/api/dir/test/hmi")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let node = resp.into_json::<serde_json::Value>().await.unwrap();
+        let confirm = node["methods"].as_array().unwrap().iter().find(|method| method["name"] == "confirm").unwrap();
+        assert_eq!(confirm["access"], "wr");
+        assert_eq!(confirm["param"], "String");
+
+        let resp = client
+            .get("/api/device/requests")
+            .header(auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::NotFound);
+
+        // A request timing out before the requests are streamed is dropped
+        let resp = client
+            .post("/api/rpc")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#"{"path": "test/hmi", "method": "confirm", "param": "Expired?"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::GatewayTimeout);
+
+        let resp = client
+            .get(format!("/api/device/requests?session_id={device_session_id}"))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let mut requests = sse_codec::decode_stream(tokio::io::BufReader::new(resp).compat());
+
+        let resp = client
+            .get("/api/device/requests")
+            .header(device_auth_header())
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Conflict);
+
+        let call = async || client
+            .post("/api/rpc")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#"{"path": "test/hmi", "method": "confirm", "param": "Proceed?"}"#)
+            .dispatch()
+            .await;
+        let mut respond = async |body: &'static str| {
+            let event = requests
+                .next()
+                .await
+                .expect("Unexpected end of stream")
+                .unwrap_or_else(|e| panic!("Unexpected error in event stream: {e}"));
+            let sse_codec::Event::Message{ event, data, .. } = event else {
+                panic!("Unexpected event");
+            };
+            assert_eq!(event, "request");
+            let request = serde_json::from_str::<serde_json::Value>(&data).unwrap();
+            assert_eq!(request["method"], "confirm");
+            assert_eq!(request["param"], "Proceed?");
+            let resp = client
+                .post(format!("/api/device/requests/{}", request["request_id"]))
+                .header(device_auth_header())
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+                .await;
+            assert_eq!(resp.status(), Status::NoContent);
+            request["request_id"].as_u64().unwrap()
+        };
+
+        let (resp, request_id) = rocket::futures::join!(call(), respond(r#"{"result": true}"#));
+        assert_eq!(request_id, 2);
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(resp.into_string().await.unwrap(), "true");
+
+        let resp = client
+            .post(format!("/api/device/requests/{request_id}"))
+            .header(device_auth_header())
+            .header(ContentType::JSON)
+            .body(r#"{"result": true}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::NotFound);
+
+        let (resp, _) = rocket::futures::join!(call(), respond(r#"{"error": {"message": "Rejected by the operator"}}"#));
+        assert_eq!(resp.status(), Status::InternalServerError);
+        let body = resp.into_json::<ErrorResponseBody>().await.unwrap();
+        let rpc_error = body.rpc_error.unwrap();
+        assert_eq!(rpc_error.name, "MethodCallException");
+        assert_eq!(rpc_error.message, "Rejected by the operator");
+
+        // Unanswered requests time out
+        let resp = call().await;
+        assert_eq!(resp.status(), Status::GatewayTimeout);
+        let body = resp.into_json::<ErrorResponseBody>().await.unwrap();
+        assert_eq!(body.rpc_error.unwrap().name, "MethodCallTimeout");
+
+        let resp = client
+            .post("/api/rpc")
+            .header(auth_header())
+            .header(ContentType::JSON)
+            .body(r#"{"path": "test/hmi", "method": "reboot"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::NotFound);
+    });
+}
+
+#[test]
+fn api_device_signal() {
+    shared_rt_test(async {
+        let client = RocketClient::untracked(build_rocket(program_config())).await.unwrap();
+
+        let login = async |body: &str| client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await
+            .into_json::<LoginResponse>()
+            .await
+            .unwrap()
+            .session_id;
+        let device_session_id = login(r#"{"username": "admin", "password": "admin", "device": {"mount": "test/producer"}}"#).await;
+        let session_id = login(r#"{"username": "admin", "password": "admin"}"#).await;
+        let emit = async |session_id: &str, body: &'static str| client
+            .post("/api/device/signals")
+            .header(rocket::http::Header::new("Authorization", session_id.to_string()))
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await
+            .status();
+
+        let resp = client
+            .post("/api/subscribe")
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(ContentType::JSON)
+            .body(r#"{"shv_ri": "test/producer/**:*:*"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let mut reader = sse_codec::decode_stream(tokio::io::BufReader::new(resp).compat());
+
+        assert_eq!(emit(&device_session_id, r#"{"path": "temperature", "signal": "chng", "param": 21}"#).await, Status::NoContent);
+        let event = reader
+            .next()
+            .await
+            .expect("Unexpected end of stream")
+            .unwrap_or_else(|e| panic!("Unexpected error in event stream: {e}"));
+        let sse_codec::Event::Message{ data, .. } = event else {
+            panic!("Unexpected event");
+        };
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&data).unwrap(), serde_json::json!({"path": "test/producer/temperature", "signal": "chng", "param": 21}));
+
+        assert_eq!(emit(&device_session_id, r#"{"path": "../device/value", "signal": "chng", "param": 1}"#).await, Status::Forbidden);
+        assert_eq!(emit(&device_session_id, r#"{"path": "/temperature", "signal": "chng"}"#).await, Status::UnprocessableEntity);
+        assert_eq!(emit(&device_session_id, r#"{"path": "temperature"}"#).await, Status::UnprocessableEntity);
+        assert_eq!(emit(&session_id, r#"{"path": "temperature", "signal": "chng", "param": 1}"#).await, Status::NotFound);
+    });
+}
+
+#[test]
+fn api_device_restrictions() {
+    shared_rt_test(async {
+        async fn login<'c>(client: &'c RocketClient, body: &'static str) -> rocket::local::asynchronous::LocalResponse<'c> {
+            client
+                .post("/api/login")
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+                .await
+        }
+
+        let client = RocketClient::untracked(build_rocket(ProgramConfig { read_only: true, ..program_config() })).await.unwrap();
+        let resp = login(&client, r#"{"username": "admin", "password": "admin", "device": {"mount": "test/restricted"}}"#).await;
+        assert_eq!(resp.status(), Status::Forbidden);
+        assert_eq!(
+            resp.into_json::<ErrorResponseBody>().await.unwrap().detail,
+            "The gateway is read-only and the session cannot be mounted as a device"
+        );
+
+        let policy = Policy::from_json(r#"{"deny": ["test/restricted/**:*", "test/permitted/secret:*:*"]}"#).unwrap();
+        let client = RocketClient::untracked(build_rocket(ProgramConfig { policy: Some(policy), ..program_config() })).await.unwrap();
+        let resp = login(&client, r#"{"username": "admin", "password": "admin", "device": {"mount": "test/restricted", "methods": [{"name": "confirm"}]}}"#).await;
+        assert_eq!(resp.status(), Status::Forbidden);
+        assert_eq!(
+            resp.into_json::<ErrorResponseBody>().await.unwrap().detail,
+            "`test/restricted:confirm` is denied by the gateway policy rule `test/restricted/**:*`"
+        );
+        let resp = login(&client, r#"{"username": "admin", "password": "admin", "device": {"mount": "test/permitted", "methods": [{"name": "confirm"}]}}"#).await;
+        assert_eq!(resp.status(), Status::Ok);
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let emit = async |body: &'static str| client
+            .post("/api/device/signals")
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await;
+        assert_eq!(emit(r#"{"path": "public", "signal": "chng", "param": 1}"#).await.status(), Status::NoContent);
+        let resp = emit(r#"{"path": "secret", "signal": "chng", "param": 1}"#).await;
+        assert_eq!(resp.status(), Status::Forbidden);
+        assert_eq!(
+            resp.into_json::<ErrorResponseBody>().await.unwrap().detail,
+            "`test/permitted/secret:get:chng` is denied by the gateway policy rule `test/permitted/secret:*:*`"
+        );
+
+        let client = RocketClient::untracked(build_rocket(ProgramConfig { device_requests_capacity: 1, ..program_config() })).await.unwrap();
+        let resp = login(&client, r#"{"username": "admin", "password": "admin", "device": {"mount": "test/queue", "methods": [{"name": "confirm"}]}}"#).await;
+        let device_session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let resp = login(&client, r#"{"username": "admin", "password": "admin"}"#).await;
+        let session_id = resp.into_json::<LoginResponse>().await.unwrap().session_id;
+        let call = async || client
+            .post("/api/rpc")
+            .header(rocket::http::Header::new("Authorization", session_id.clone()))
+            .header(ContentType::JSON)
+            .body(r#"{"path": "test/queue", "method": "confirm"}"#)
+            .dispatch()
+            .await;
+        let next_request_id = async || {
+            let resp = client
+                .get(format!("/api/device/requests?session_id={device_session_id}"))
+                .dispatch()
+                .await;
+            let mut requests = sse_codec::decode_stream(tokio::io::BufReader::new(resp).compat());
+            let Some(Ok(sse_codec::Event::Message { data, .. })) = requests.next().await else {
+                panic!("Unexpected end of stream");
+            };
+            serde_json::from_str::<serde_json::Value>(&data).unwrap()["request_id"].as_u64().unwrap()
+        };
+        let (resp, rejected_resp, ()) = rocket::futures::join!(call(), async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            call().await
+        }, async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            // The request is delivered again by a new stream if the first one closes
+            let request_id = next_request_id().await;
+            assert_eq!(next_request_id().await, request_id);
+            let resp = client
+                .post(format!("/api/device/requests/{request_id}"))
+                .header(rocket::http::Header::new("Authorization", device_session_id.clone()))
+                .header(ContentType::JSON)
+                .body(r#"{"result": true}"#)
+                .dispatch()
+                .await;
+            assert_eq!(resp.status(), Status::NoContent);
+        });
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(rejected_resp.status(), Status::ServiceUnavailable);
+        assert_eq!(rejected_resp.into_json::<ErrorResponseBody>().await.unwrap().rpc_error.unwrap().name, "TryAgainLater");
+    });
+}